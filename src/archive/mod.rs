@@ -0,0 +1,492 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::config;
+use crate::error::{CliError, CliResult};
+use crate::privilege::Escalation;
+use crate::ui;
+
+/// Returns true if `link`, resolved relative to `base` inside the archive root,
+/// cannot point outside of that root.
+fn stays_within(base: &Path, link: &Path) -> bool {
+    if link.is_absolute() {
+        return false;
+    }
+
+    let mut depth: usize = 0;
+    for component in base.join(link).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+pub fn unpack(archive_path: &Path, dest: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fs::File::open(archive_path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    archive.set_unpack_xattrs(false);
+    // Without full preservation the mode is masked to 0o777: executable bits
+    // survive, setuid/setgid bits do not (the tree may end up copied by root)
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(false);
+
+    fs::create_dir_all(dest)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let kind = entry.header().entry_type();
+
+        // Symlinks resolve relative to their own directory, hardlinks relative to the archive root
+        if kind.is_symlink() || kind.is_hard_link() {
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| format!("link entry {} has no target", path.display()))?
+                .into_owned();
+            let base = if kind.is_symlink() { path.parent().unwrap_or(Path::new("")) } else { Path::new("") };
+            if !stays_within(base, &link) {
+                return Err(format!(
+                    "refusing to extract {}: link target {} escapes the destination",
+                    path.display(),
+                    link.display()
+                )
+                .into());
+            }
+        }
+
+        if !entry.unpack_in(dest)? {
+            return Err(format!("refusing to extract {}: path escapes the destination", path.display()).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Gzip magic bytes every `.tar.gz` starts with
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Quick sanity check that `archive_path` is a gzipped tarball: the gzip magic
+/// bytes, then a first tar header with a valid checksum. On failure returns a
+/// short description of what the file looks like instead.
+pub fn check_magic(archive_path: &Path) -> Result<(), String> {
+    let mut head = Vec::with_capacity(16);
+    fs::File::open(archive_path)
+        .and_then(|f| f.take(16).read_to_end(&mut head))
+        .map_err(|e| e.to_string())?;
+    if head.is_empty() {
+        return Err("an empty file".to_string());
+    }
+    if !head.starts_with(&GZIP_MAGIC) {
+        return Err(describe_bytes(&head));
+    }
+
+    let file = fs::File::open(archive_path).map_err(|e| e.to_string())?;
+    let mut block = [0u8; 512];
+    flate2::read::GzDecoder::new(file)
+        .read_exact(&mut block)
+        .map_err(|e| format!("a corrupt or truncated gzip stream ({})", e))?;
+    let header = tar::Header::from_byte_slice(&block);
+    let mut expected = header.clone();
+    expected.set_cksum();
+    match header.cksum() {
+        Ok(sum) if expected.cksum().is_ok_and(|e| e == sum) => Ok(()),
+        _ => Err("gzip data that does not contain a tar archive".to_string()),
+    }
+}
+
+/// Show the first bytes of a file as text when printable, hex otherwise
+fn describe_bytes(bytes: &[u8]) -> String {
+    if bytes.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()) {
+        format!("{:?}", String::from_utf8_lossy(bytes).trim())
+    } else {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("bytes {}", hex.join(" "))
+    }
+}
+
+/// Read a text file from the archive without extracting it. `name` is relative
+/// to the archive's top-level directory (e.g. `dependencies.toml`).
+pub fn read_file(archive_path: &Path, name: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let file = fs::File::open(archive_path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        components.next();
+        if entry.header().entry_type().is_file() && components.as_path() == Path::new(name) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+/// Read the whole gzip and tar stream without extracting anything, failing
+/// on the first corrupt or truncated part
+pub fn verify(archive_path: &Path) -> std::io::Result<()> {
+    let file = fs::File::open(archive_path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    for entry in archive.entries()? {
+        std::io::copy(&mut entry?, &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+/// `install_into`, but when it fails because the archive itself is corrupt,
+/// fetch it again with `redownload` and retry once
+pub fn install_with_retry(
+    archive_path: &Path,
+    target: &Path,
+    redownload: impl FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Err(e) = install_into(archive_path, target) else {
+        return Ok(());
+    };
+    if verify(archive_path).is_ok() {
+        return Err(e);
+    }
+    ui::warn(&format!("Downloaded archive is corrupt ({}); downloading it again", e));
+    let _ = fs::remove_file(archive_path);
+    redownload()?;
+    install_into(archive_path, target)
+}
+
+/// Move the contents of a single top-level directory (as produced by GitHub
+/// tarballs, e.g. `noctalia-shell-main/`) up into `dir` (strip-components=1).
+fn strip_top_level(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    if let [entry] = entries.as_slice() {
+        let inner = entry.path();
+        if inner.is_dir() {
+            for sub_entry in fs::read_dir(&inner)? {
+                let sub_entry = sub_entry?;
+                fs::rename(sub_entry.path(), dir.join(sub_entry.file_name()))?;
+            }
+            fs::remove_dir(&inner)?;
+        }
+    }
+    Ok(())
+}
+
+/// Collect `(relative path, size, mtime)` for every entry under `dir`,
+/// without following symlinks
+fn walk(dir: &Path, base: &Path, out: &mut Vec<(String, u64, u64)>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let meta = fs::symlink_metadata(&path)?;
+        let rel = path.strip_prefix(base).unwrap_or(&path).to_string_lossy().into_owned();
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if meta.is_dir() {
+            walk(&path, base, out)?;
+        } else {
+            out.push((rel, meta.len(), mtime));
+        }
+    }
+    Ok(())
+}
+
+/// Cheap fingerprint of an installed tree: a SHA-256 over the sorted file
+/// names, sizes and mtimes, so a hand-edited installation can be told apart
+/// from the recorded one without reading every file
+pub fn fingerprint(dir: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    files.sort();
+    let mut hasher = Sha256::new();
+    for (path, size, mtime) in &files {
+        hasher.update(format!("{}\0{}\0{}\n", path, size, mtime));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// SHA-256 of the file at `path`, as lowercase hex
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Say where the shell goes and whether an existing installation is replaced.
+/// `resolved` is `target` as returned by `resolve_target`.
+pub fn report_target(target: &Path, resolved: &Path) {
+    let display = if resolved == target {
+        target.display().to_string()
+    } else {
+        format!("{} (symlink to {})", target.display(), resolved.display())
+    };
+    if resolved.join(ENTRY_POINT).exists() {
+        ui::info(&format!("Found installation at: {}; it will be replaced", display));
+    } else {
+        ui::info(&format!("No existing installation found, will install to: {}", display));
+    }
+}
+
+/// Ask before an install deletes the non-empty directory at `target`, saying
+/// how many files go with it. `force` (or `--yes`) skips the question; without
+/// a terminal to ask on this fails rather than deleting anything.
+pub fn confirm_replace(target: &Path, force: bool) -> CliResult {
+    let mut files = Vec::new();
+    if force || walk(target, target, &mut files).is_err() || files.is_empty() {
+        return Ok(());
+    }
+    let prompt = format!("Replace {} and delete its {} file(s)?", target.display(), files.len());
+    if !ui::confirm(&prompt, false)? {
+        return Err(CliError::failure("Installation cancelled; the existing files were left untouched")
+            .with_hint("Pass --force (or --yes) to replace them without asking."));
+    }
+    Ok(())
+}
+
+/// Refuse to install over a symlinked `target` (often a developer's git
+/// checkout) unless `follow_symlink` is set, in which case the link's
+/// destination is updated in place and the link itself is kept.
+pub fn resolve_target(target: &Path, follow_symlink: bool) -> CliResult<PathBuf> {
+    let is_symlink = fs::symlink_metadata(target).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+    if !is_symlink {
+        return Ok(target.to_path_buf());
+    }
+
+    let link = fs::read_link(target).map(|p| p.display().to_string()).unwrap_or_else(|_| "?".to_string());
+    if !follow_symlink {
+        return Err(CliError::failure(format!("{} is a symlink to {}", target.display(), link))
+            .with_hint("Installing would replace the contents of the linked directory.")
+            .with_hint("Re-run with --follow-symlink to install into the link target anyway."));
+    }
+
+    let resolved = fs::canonicalize(target)
+        .map_err(|e| CliError::failure(format!("Failed to resolve symlink {}: {}", target.display(), e)))?;
+    ui::warn(&format!("{} is a symlink; installing into {}", target.display(), resolved.display()));
+    Ok(resolved)
+}
+
+/// Hidden sibling of `target` used while swapping installations, e.g.
+/// `.noctalia-shell.new-1234` next to `noctalia-shell`
+fn sibling(target: &Path, kind: &str) -> PathBuf {
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    target.with_file_name(format!(".{}.{}-{}", name, kind, std::process::id()))
+}
+
+/// Remove staging directories left next to `target` by interrupted installs.
+/// Backups of the previous installation are only removed if `target` exists.
+pub fn clean_leftovers(target: &Path) {
+    let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else { return };
+    let name = name.to_string_lossy();
+    let staging = format!(".{}.new-", name);
+    let backup = format!(".{}.old-", name);
+    let Ok(entries) = fs::read_dir(parent) else { return };
+    for entry in entries.flatten() {
+        let entry_name = entry.file_name().to_string_lossy().into_owned();
+        if entry_name.starts_with(&staging) || (entry_name.starts_with(&backup) && target.exists()) {
+            ui::info(&format!("Removing leftover {}", entry.path().display()));
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// File every complete shell installation has at its root
+pub const ENTRY_POINT: &str = "shell.qml";
+
+/// Repair what an interrupted install may have left at `target`: restore a
+/// backup orphaned mid-swap, drop staging directories, and flatten a tree that
+/// was never stripped of its `noctalia-shell-*/` top-level directory.
+pub fn recover_partial(target: &Path) {
+    // Never rearrange a linked directory (see `resolve_target`)
+    if fs::symlink_metadata(target).is_ok_and(|m| m.file_type().is_symlink()) {
+        return;
+    }
+    let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else { return };
+    let backup_prefix = format!(".{}.old-", name.to_string_lossy());
+
+    if !target.exists()
+        && let Ok(entries) = fs::read_dir(parent)
+        && let Some(backup) = entries
+            .flatten()
+            .find(|e| e.file_name().to_string_lossy().starts_with(&backup_prefix))
+        && fs::rename(backup.path(), target).is_ok()
+    {
+        ui::info(&format!("Recovered previous installation from {}", backup.path().display()));
+    }
+
+    clean_leftovers(target);
+
+    let Ok(entries) = fs::read_dir(target).and_then(|e| e.collect::<Result<Vec<_>, _>>()) else { return };
+    if let [entry] = entries.as_slice()
+        && entry.file_name().to_string_lossy().starts_with("noctalia-shell-")
+        && entry.path().is_dir()
+    {
+        match strip_top_level(target) {
+            Ok(()) => ui::info(&format!("Flattened leftover {} from an interrupted install", entry.path().display())),
+            Err(e) => ui::warn(&format!("Could not flatten {}: {}", entry.path().display(), e)),
+        }
+    }
+
+    if !target.join(ENTRY_POINT).exists() && target.exists() {
+        ui::warn(&format!("{} looks incomplete (no {}); it will be replaced", target.display(), ENTRY_POINT));
+    }
+}
+
+/// Replace the installation at `target` with the contents of `archive_path`.
+/// The new tree is fully extracted next to `target` and swapped in with renames,
+/// so a failed install leaves the previous one untouched. Targets outside the
+/// user's home (e.g. `/etc/xdg`) are written through sudo.
+pub fn install_into(archive_path: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let needs_sudo = target.starts_with("/etc");
+    let target_str = target.to_str().ok_or("installation path is not valid UTF-8")?;
+    let staging = sibling(target, "new");
+    let backup = sibling(target, "old");
+
+    if !needs_sudo {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _ = fs::remove_dir_all(&staging);
+        if let Err(e) = unpack(archive_path, &staging).and_then(|_| strip_top_level(&staging)) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+
+        if target.exists()
+            && let Err(e) = fs::rename(target, &backup)
+        {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.into());
+        }
+        if let Err(e) = fs::rename(&staging, target) {
+            // Put the previous installation back
+            let _ = fs::rename(&backup, target);
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.into());
+        }
+        let _ = fs::remove_dir_all(&backup);
+        return Ok(());
+    }
+
+    // System location: extract to a temp directory first, then swap it in as root
+    let temp_dir = config::temp_dir().join(format!("noctalia-shell-update-{}", std::process::id()));
+    let staged = temp_dir.join("noctalia-shell");
+    let result = unpack(archive_path, &staged)
+        .and_then(|_| strip_top_level(&staged))
+        .and_then(|_| {
+            let staged_str = staged.to_str().ok_or("temporary path is not valid UTF-8")?;
+            let parent = target.parent().and_then(Path::to_str).unwrap_or("/");
+            let new = staging.to_str().ok_or("installation path is not valid UTF-8")?;
+            let old = backup.to_str().ok_or("installation path is not valid UTF-8")?;
+            let escalation = Escalation::detect()?;
+            escalation.announce();
+            // Plain argv, so no path is ever parsed by a shell running as root
+            escalation.run(&["mkdir", "-p", parent])?;
+            escalation.run(&["rm", "-rf", new, old])?;
+            // Keep modes and mtimes like cp -a (plain -p, so busybox and BSD cp work
+            // too), then hand the files to root since -p also keeps the owner
+            escalation.run(&["cp", "-Rp", staged_str, new])?;
+            escalation.run(&["chown", "-R", "0:0", new])?;
+            // Swap the new tree in; on failure put the previous installation back
+            let had_target = target.exists();
+            if had_target && let Err(e) = escalation.run(&["mv", target_str, old]) {
+                let _ = escalation.run(&["rm", "-rf", new]);
+                return Err(e);
+            }
+            if let Err(e) = escalation.run(&["mv", new, target_str]) {
+                if had_target {
+                    let _ = escalation.run(&["mv", old, target_str]);
+                }
+                let _ = escalation.run(&["rm", "-rf", new]);
+                return Err(e);
+            }
+            if had_target && let Err(e) = escalation.run(&["rm", "-rf", old]) {
+                ui::warn(&format!("Could not remove the previous installation at {}: {}", old, e));
+            }
+            Ok(())
+        });
+    let _ = fs::remove_dir_all(&temp_dir);
+    result.map_err(|e| format!("Failed to install files into {}: {}", target.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `.tar.gz` in `dir` holding whatever `build` appends
+    fn tarball(dir: &Path, build: impl FnOnce(&mut tar::Builder<flate2::write::GzEncoder<fs::File>>)) -> PathBuf {
+        let path = dir.join("test.tar.gz");
+        let file = fs::File::create(&path).unwrap();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+        build(&mut builder);
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn unpack_refuses_symlink_out_of_the_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tarball(dir.path(), |b| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            b.append_link(&mut header, "noctalia-shell/passwd", "/etc/passwd").unwrap();
+        });
+
+        let dest = dir.path().join("out");
+        let err = unpack(&archive, &dest).unwrap_err();
+        assert!(err.to_string().contains("escapes the destination"), "{}", err);
+        assert!(fs::symlink_metadata(dest.join("noctalia-shell/passwd")).is_err());
+    }
+
+    #[test]
+    fn unpack_keeps_executable_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tarball(dir.path(), |b| {
+            for (name, mode) in [("noctalia-shell/run.sh", 0o755), ("noctalia-shell/shell.qml", 0o644)] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(2);
+                header.set_mode(mode);
+                b.append_data(&mut header, name, &b"x\n"[..]).unwrap();
+            }
+        });
+
+        let dest = dir.path().join("out");
+        unpack(&archive, &dest).unwrap();
+        let mode = |name: &str| fs::metadata(dest.join(name)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("noctalia-shell/run.sh") & 0o111, 0o111);
+        assert_eq!(mode("noctalia-shell/shell.qml") & 0o111, 0);
+    }
+
+    #[test]
+    fn resolve_target_follows_a_symlink_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkout = dir.path().join("checkout");
+        fs::create_dir(&checkout).unwrap();
+        let link = dir.path().join("noctalia-shell");
+        std::os::unix::fs::symlink(&checkout, &link).unwrap();
+
+        assert!(resolve_target(&link, false).is_err());
+        assert_eq!(resolve_target(&link, true).unwrap(), fs::canonicalize(&checkout).unwrap());
+        assert_eq!(resolve_target(&checkout, false).unwrap(), checkout);
+    }
+}
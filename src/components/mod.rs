@@ -0,0 +1,48 @@
+use crate::config;
+use crate::error::CliResult;
+use crate::output::{self, ComponentRow, Components};
+use crate::ui;
+use crate::update::shell::display_version;
+
+pub fn run() -> CliResult {
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+
+    let mut components: Vec<_> = cfg.components.iter().collect();
+    components.sort_by_key(|(name, _)| name.as_str());
+
+    let rows: Vec<ComponentRow> = components
+        .iter()
+        .map(|(name, c)| ComponentRow {
+            name: name.to_string(),
+            installed: c.installed,
+            source: c.source.to_string(),
+            default_source: c.default_source.map(|s| s.to_string()),
+            version: c.version.clone(),
+            commit: c.commit.clone(),
+        })
+        .collect();
+
+    if ui::is_json() {
+        return output::print(&Components { components: rows });
+    }
+
+    ui::section("Noctalia Components");
+    if rows.is_empty() {
+        ui::info("No components recorded in the config yet");
+        return Ok(());
+    }
+
+    let table: Vec<[String; 4]> = components
+        .iter()
+        .map(|(name, c)| {
+            [
+                name.to_string(),
+                if c.installed { "yes" } else { "no" }.to_string(),
+                c.source.to_string(),
+                c.version.as_deref().map(|v| display_version(c.source, v)).unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    output::table(["NAME", "INSTALLED", "SOURCE", "VERSION"], &table);
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use std::{env, process::Command};
+
+use crate::SourceKind;
+use crate::error::{CliError, CliResult, ExitCode};
+use crate::install::systemd;
+use crate::output::{self, SettingRow, Settings};
+use crate::{http, ui};
+
+use super::{CliConfig, KNOWN_COMPONENTS, Origin, check_component, layered, process_env};
+
+/// Per-component fields readable with `config get <component>.<field>`
+const GET_FIELDS: &[&str] = &["source", "default_source", "installed", "version", "commit", "fingerprint"];
+
+/// Print the recorded value of a dotted key such as `shell.version`, or
+/// nothing and exit 3 when it is unset
+pub fn get(key: &str) -> CliResult {
+    let valid = || {
+        let keys: Vec<String> =
+            KNOWN_COMPONENTS.iter().flat_map(|c| GET_FIELDS.iter().map(move |f| format!("{}.{}", c, f))).collect();
+        format!("Valid keys: {}", keys.join(", "))
+    };
+    let (component, field) = key
+        .split_once('.')
+        .filter(|(component, field)| KNOWN_COMPONENTS.contains(component) && GET_FIELDS.contains(field))
+        .ok_or_else(|| CliError::usage(format!("Unknown config key '{}'", key)).with_hint(valid()))?;
+
+    let (cfg, _path) = CliConfig::load().expect("load config");
+    let Some(entry) = cfg.components.get(component) else {
+        return Err(CliError::silent(ExitCode::NotFound));
+    };
+    let value = match field {
+        "source" => Some(entry.source.to_string()),
+        "default_source" => entry.default_source.map(|s| s.to_string()),
+        "installed" => Some(entry.installed.to_string()),
+        "version" => entry.version.clone(),
+        "commit" => entry.commit.clone(),
+        _ => entry.fingerprint.clone(),
+    };
+    match value {
+        Some(value) => {
+            println!("{}", value);
+            Ok(())
+        }
+        None => Err(CliError::silent(ExitCode::NotFound)),
+    }
+}
+
+/// Set (or with `None`, clear) the source install/update use when neither
+/// --git nor --release is given
+pub fn set_source(component: &str, source: Option<SourceKind>) -> CliResult {
+    check_component(component)?;
+    let (mut cfg, path) = CliConfig::load().expect("load config");
+    cfg.set_default_source(component, source);
+    cfg.save(&path)
+        .map_err(|e| CliError::failure(format!("Failed to save config {}: {}", path.display(), e)))?;
+
+    match source {
+        Some(source) => ui::success(&format!("Default source for {} set to {}", component, source)),
+        None => ui::success(&format!("Cleared the default source for {}; you will be asked next time", component)),
+    }
+    Ok(())
+}
+
+fn row(key: impl Into<String>, value: impl ToString, origin: Origin) -> SettingRow {
+    SettingRow { key: key.into(), value: value.to_string(), source: origin.to_string() }
+}
+
+/// A plain config value: from the file when it differs from the default
+fn plain<T: ToString + PartialEq + Default>(key: &str, value: T) -> SettingRow {
+    let origin = if value == T::default() { Origin::Default } else { Origin::Config };
+    row(key, value, origin)
+}
+
+/// A value overridable by `env_var`, falling back to `default`
+fn with_env(
+    key: &str,
+    env: impl Fn(&str) -> Option<String>,
+    env_var: &'static str,
+    configured: Option<String>,
+    default: &str,
+) -> SettingRow {
+    match layered(env, env_var, configured) {
+        Some((value, origin)) => row(key, value, origin),
+        None => row(key, default, Origin::Default),
+    }
+}
+
+/// Print every setting as the CLI will use it, with where it comes from
+pub fn effective() -> CliResult {
+    let (cfg, path) = CliConfig::load().expect("load config");
+    let mut rows = vec![row("config_file", path.display(), Origin::Default)];
+
+    rows.push(with_env(
+        "source.api_base",
+        process_env,
+        "NOCTALIA_API_BASE",
+        cfg.source.api_base.clone(),
+        http::DEFAULT_API_BASE,
+    ));
+    rows.push(with_env(
+        "source.codeload_base",
+        process_env,
+        "NOCTALIA_CODELOAD_BASE",
+        cfg.source.codeload_base.clone(),
+        http::DEFAULT_CODELOAD_BASE,
+    ));
+    rows.push(with_env(
+        "http.user_agent",
+        process_env,
+        "NOCTALIA_USER_AGENT",
+        cfg.http.user_agent.clone(),
+        &http::default_user_agent(),
+    ));
+    rows.push(match cfg.http.max_download_mb {
+        Some(mb) => row("http.max_download_mb", mb, Origin::Config),
+        None => row("http.max_download_mb", http::DEFAULT_MAX_DOWNLOAD_BYTES / (1024 * 1024), Origin::Default),
+    });
+    rows.push(match (http::limit_rate_flag(), &cfg.http.limit_rate) {
+        (Some(rate), _) => row("http.limit_rate", rate, Origin::Flag),
+        (None, Some(rate)) => row("http.limit_rate", rate, Origin::Config),
+        (None, None) => row("http.limit_rate", "unlimited", Origin::Default),
+    });
+    rows.push(match http::ca_bundle() {
+        Some((path, origin)) => row("http.ca_bundle", path.display(), origin),
+        None => row("http.ca_bundle", "system", Origin::Default),
+    });
+
+    let (cache_dir, origin) = super::cache_dir_origin();
+    rows.push(row("cache_dir", cache_dir.display(), origin));
+    rows.push(plain("cache.keep_downloads", cfg.cache.keep_downloads));
+    rows.push(plain("cache.max_age_days", cfg.cache.max_age_days));
+    let tmpdir = env::var("TMPDIR").ok().filter(|v| !v.is_empty());
+    rows.push(match (&cfg.cache.temp_dir, tmpdir) {
+        (Some(dir), _) => row("cache.temp_dir", dir.display(), Origin::Config),
+        (None, Some(dir)) => row("cache.temp_dir", dir, Origin::Env("TMPDIR")),
+        (None, None) => row("cache.temp_dir", super::temp_dir().display(), Origin::Default),
+    });
+
+    rows.push(plain("notify.enabled", cfg.notify.enabled));
+    rows.push(plain("shell.trust_config_only", cfg.shell.trust_config_only));
+    rows.push(plain("update.changelog", cfg.update.changelog));
+    rows.push(match (&cfg.dependencies.ubuntu_ppa, cfg.dependencies.quickshell_ppa()) {
+        (Some(_), Some(ppa)) => row("dependencies.ubuntu_ppa", ppa, Origin::Config),
+        (Some(_), None) => row("dependencies.ubuntu_ppa", "none", Origin::Config),
+        (None, _) => row("dependencies.ubuntu_ppa", super::DEFAULT_UBUNTU_PPA, Origin::Default),
+    });
+    let (unit_dir, origin) = systemd::unit_dir();
+    rows.push(row("systemd.unit_dir", unit_dir.display(), origin));
+    if let Some((distro, origin)) = layered(process_env, "NOCTALIA_DISTRO", None) {
+        rows.push(row("distro", distro, origin));
+    }
+
+    for (name, value) in &cfg.run.env {
+        rows.push(row(format!("run.env.{}", name), value, Origin::Config));
+    }
+    let mut components: Vec<_> = cfg.components.iter().collect();
+    components.sort_by_key(|(name, _)| name.as_str());
+    for (name, component) in components {
+        let key = format!("components.{}.default_source", name);
+        rows.push(match component.default_source {
+            Some(source) => row(key, source, Origin::Config),
+            None => row(key, "ask", Origin::Default),
+        });
+    }
+
+    if ui::is_json() {
+        return output::print(&Settings { settings: rows });
+    }
+
+    ui::section("Effective Configuration");
+    let key_width = rows.iter().map(|r| r.key.len()).max().unwrap_or(0);
+    let value_width = rows.iter().map(|r| r.value.len()).max().unwrap_or(0);
+    for r in &rows {
+        println!("{:<key_width$}  {:<value_width$}  ({})", r.key, r.value, r.source);
+    }
+    Ok(())
+}
+
+/// `$EDITOR`, then `$VISUAL`, then nano if installed, else vi
+fn editor() -> CliResult<Vec<String>> {
+    let configured = ["EDITOR", "VISUAL"].into_iter().find_map(|var| env::var(var).ok().filter(|v| !v.trim().is_empty()));
+    let Some(editor) = configured else {
+        let nano = env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join("nano").is_file()));
+        return Ok(vec![if nano { "nano" } else { "vi" }.to_string()]);
+    };
+    // Allow editors with arguments, such as `code --wait`
+    shell_words::split(&editor).map_err(|e| CliError::usage(format!("Cannot parse editor command '{}': {}", editor, e)))
+}
+
+/// Open the config file in an editor, creating it first if needed, then
+/// report whether the result still parses
+pub fn edit() -> CliResult {
+    let (cfg, path) = CliConfig::load().expect("load config");
+    if !path.exists() {
+        cfg.save(&path)
+            .map_err(|e| CliError::failure(format!("Failed to create {}: {}", path.display(), e)))?;
+    }
+
+    let command = editor()?;
+    let (program, args) = command.split_first().ok_or_else(|| CliError::usage("The editor command is empty"))?;
+    let status = Command::new(program)
+        .args(args)
+        .arg(&path)
+        .status()
+        .map_err(|e| CliError::failure(format!("Failed to run {}: {}", program, e)).with_hint("Set $EDITOR to your editor."))?;
+    if !status.success() {
+        return Err(CliError::failure(format!("{} exited with {}", program, status)));
+    }
+
+    match CliConfig::check_file(&path) {
+        Ok(()) => {
+            ui::success(&format!("{} is valid", path.display()));
+            Ok(())
+        }
+        Err(e) => Err(CliError::failure(e)
+            .with_hint("Until it is fixed, the CLI falls back to default settings.")
+            .with_hint("Fix it with: noctalia config edit")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_env_falls_back_to_default() {
+        const VAR: &str = "NOCTALIA_TEST_WITH_ENV";
+        let cases = [
+            (Some("env"), Some("config"), "env", "env NOCTALIA_TEST_WITH_ENV"),
+            (None, Some("config"), "config", "config"),
+            (None, None, "default", "default"),
+        ];
+        for (env_value, configured, value, source) in cases {
+            let env = |name: &str| env_value.filter(|_| name == VAR).map(str::to_string);
+            let got = with_env("key", env, VAR, configured.map(str::to_string), "default");
+            assert_eq!((got.value.as_str(), got.source.as_str()), (value, source));
+        }
+    }
+}
@@ -1,19 +1,32 @@
-use std::{collections::HashMap, env, fs, io, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+use crate::error::{CliError, CliResult};
+use crate::ui;
+
+pub mod command;
+pub mod source;
+
+pub use source::{SourceFlags, SourceSpec};
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq, clap::ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum SourceKind {
+    #[default]
     Release,
     Git,
 }
 
-impl Default for SourceKind {
-    fn default() -> Self { SourceKind::Release }
-}
-
 impl std::fmt::Display for SourceKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -25,15 +38,218 @@ impl std::fmt::Display for SourceKind {
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ComponentConfig {
+    /// Source of the current installation
     pub source: SourceKind,
+    /// Source used by install/update when no flag is given, set with
+    /// `noctalia config set-source`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_source: Option<SourceKind>,
     pub installed: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Update downloaded with `update --download-only`, waiting to be applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staged: Option<StagedUpdate>,
+    /// Result of the most recent update check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<UpdateCheck>,
+    /// `archive::fingerprint` of the tree as installed, to detect local edits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    pub source: SourceKind,
+    /// Latest version (tag or commit) found by the check
+    pub latest: String,
+    /// Unix time of the check, in seconds
+    pub checked_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StagedUpdate {
+    pub source: SourceKind,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Cached tarball to extract
+    pub archive: PathBuf,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Maximum size of a downloaded archive in MiB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_download_mb: Option<u64>,
+    /// User agent sent to GitHub instead of the default `noctalia-cli/<version>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Download speed cap such as `500k` or `2M` (bytes per second)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_rate: Option<String>,
+    /// PEM file of extra CA certificates to trust, e.g. a proxy's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+}
+
+impl HttpConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceConfig {
+    /// Base URL of the GitHub API (or a mirror of it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_base: Option<String>,
+    /// Base URL serving repository tarballs (codeload or a mirror of it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codeload_base: Option<String>,
+}
+
+impl SourceConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Never delete downloaded tarballs after extraction
+    #[serde(default)]
+    pub keep_downloads: bool,
+    /// Age in days after which downloaded tarballs are pruned (0 removes them right away)
+    #[serde(default)]
+    pub max_age_days: u32,
+    /// Scratch directory for extracting archives before they are moved into place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// Send a desktop notification when install/update finishes
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+impl NotifyConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// PPA offered on Ubuntu to install quickshell from when none is configured
+pub const DEFAULT_UBUNTU_PPA: &str = "errornointernet/quickshell";
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependenciesConfig {
+    /// PPA (`owner/name`) offered on Ubuntu to install quickshell from, in
+    /// place of `DEFAULT_UBUNTU_PPA`; empty to offer none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ubuntu_ppa: Option<String>,
+}
+
+impl DependenciesConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The PPA to offer for quickshell, `None` if disabled with an empty value
+    pub fn quickshell_ppa(&self) -> Option<&str> {
+        match self.ubuntu_ppa.as_deref() {
+            Some("") => None,
+            Some(ppa) => Some(ppa),
+            None => Some(DEFAULT_UBUNTU_PPA),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateConfig {
+    /// Print the commits between the installed and latest git revision
+    #[serde(default)]
+    pub changelog: bool,
+}
+
+impl UpdateConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunConfig {
+    /// Extra environment variables passed to quickshell by `noctalia run`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl RunConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemdConfig {
+    /// Directory the user unit is installed into instead of the detected one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_dir: Option<PathBuf>,
+}
+
+impl SystemdConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShellConfig {
+    /// Believe the recorded `installed` flag instead of probing the
+    /// filesystem, e.g. when a leftover directory is not a real install
+    #[serde(default)]
+    pub trust_config_only: bool,
+}
+
+impl ShellConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct CliConfig {
+    #[serde(default)]
     pub components: HashMap<String, ComponentConfig>,
+    #[serde(default, skip_serializing_if = "SourceConfig::is_default")]
+    pub source: SourceConfig,
+    #[serde(default, skip_serializing_if = "HttpConfig::is_default")]
+    pub http: HttpConfig,
+    #[serde(default, skip_serializing_if = "CacheConfig::is_default")]
+    pub cache: CacheConfig,
+    #[serde(default, skip_serializing_if = "NotifyConfig::is_default")]
+    pub notify: NotifyConfig,
+    #[serde(default, skip_serializing_if = "UpdateConfig::is_default")]
+    pub update: UpdateConfig,
+    #[serde(default, skip_serializing_if = "DependenciesConfig::is_default")]
+    pub dependencies: DependenciesConfig,
+    #[serde(default, skip_serializing_if = "RunConfig::is_default")]
+    pub run: RunConfig,
+    #[serde(default, skip_serializing_if = "SystemdConfig::is_default")]
+    pub systemd: SystemdConfig,
+    #[serde(default, skip_serializing_if = "ShellConfig::is_default")]
+    pub shell: ShellConfig,
 }
 
 impl CliConfig {
@@ -48,12 +264,32 @@ impl CliConfig {
         }
     }
 
+    /// Check that the file at `path` parses, describing the problem if not
+    pub fn check_file(path: &Path) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str::<CliConfig>(&content)
+            .map(|_| ())
+            .map_err(|e| format!("{} is invalid: {}", path.display(), e.message()))
+    }
+
     pub fn save(&self, to: &PathBuf) -> io::Result<()> {
         if let Some(parent) = to.parent() { fs::create_dir_all(parent)?; }
         let serialized = toml::to_string_pretty(self).unwrap_or_default();
         fs::write(to, serialized)
     }
 
+    /// Save, warning (once per run) instead of failing when the config can't
+    /// be written, so the operation itself still succeeds
+    pub fn save_or_warn(&self, to: &PathBuf) {
+        static WARNED: AtomicBool = AtomicBool::new(false);
+        if let Err(e) = self.save(to)
+            && !WARNED.swap(true, Ordering::Relaxed)
+        {
+            ui::warn(&format!("Could not save the CLI config to {}: {}", to.display(), e));
+            ui::warn("Source choices and installed versions will not be remembered.");
+        }
+    }
+
     pub fn get_component_source(&self, component: &str) -> Option<SourceKind> {
         self.components.get(component).map(|c| c.source)
     }
@@ -63,6 +299,15 @@ impl CliConfig {
         entry.source = source;
     }
 
+    pub fn get_default_source(&self, component: &str) -> Option<SourceKind> {
+        self.components.get(component).and_then(|c| c.default_source)
+    }
+
+    pub fn set_default_source(&mut self, component: &str, source: Option<SourceKind>) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        entry.default_source = source;
+    }
+
     pub fn set_installed(&mut self, component: &str, installed: bool) {
         let entry = self.components.entry(component.to_string()).or_default();
         entry.installed = installed;
@@ -77,18 +322,56 @@ impl CliConfig {
         entry.version = Some(version);
     }
 
+    pub fn get_component_commit(&self, component: &str) -> Option<String> {
+        self.components.get(component).and_then(|c| c.commit.clone())
+    }
+
+    pub fn set_component_commit(&mut self, component: &str, commit: Option<String>) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        entry.commit = commit;
+    }
+
+    pub fn get_fingerprint(&self, component: &str) -> Option<String> {
+        self.components.get(component).and_then(|c| c.fingerprint.clone())
+    }
+
+    pub fn set_fingerprint(&mut self, component: &str, fingerprint: Option<String>) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        entry.fingerprint = fingerprint;
+    }
+
+    pub fn get_staged_update(&self, component: &str) -> Option<StagedUpdate> {
+        self.components.get(component).and_then(|c| c.staged.clone())
+    }
+
+    pub fn set_staged_update(&mut self, component: &str, staged: Option<StagedUpdate>) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        entry.staged = staged;
+    }
+
+    pub fn get_last_checked(&self, component: &str) -> Option<UpdateCheck> {
+        self.components.get(component).and_then(|c| c.last_checked.clone())
+    }
+
+    pub fn set_last_checked(&mut self, component: &str, check: UpdateCheck) {
+        let entry = self.components.entry(component.to_string()).or_default();
+        entry.last_checked = Some(check);
+    }
+
     pub fn is_component_installed(&self, component: &str) -> bool {
         // For shell component, also check if it actually exists on the filesystem
-        if component == "shell" {
+        // unless `[shell] trust_config_only` makes the recorded flag authoritative
+        if component == "shell" && !self.shell.trust_config_only {
             let filesystem_installed = check_shell_installed();
             let config_installed = self.components.get("shell").map(|c| c.installed).unwrap_or(false);
             
             // If filesystem says installed but config says not, update the config
-            if filesystem_installed && !config_installed {
-                if let Ok((mut updated_cfg, path)) = CliConfig::load() {
-                    updated_cfg.set_installed("shell", true);
-                    let _ = updated_cfg.save(&path);
-                }
+            if filesystem_installed
+                && !config_installed
+                && let Ok((mut updated_cfg, path)) = CliConfig::load()
+            {
+                updated_cfg.set_installed("shell", true);
+                updated_cfg.save_or_warn(&path);
             }
             
             return filesystem_installed;
@@ -99,18 +382,95 @@ impl CliConfig {
 
 }
 
+/// Where an effective setting comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    Config,
+    Env(&'static str),
+    Flag,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Config => write!(f, "config"),
+            Origin::Env(name) => write!(f, "env {}", name),
+            Origin::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// The process environment, as looked up by `layered` and `flagged`
+pub fn process_env(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// Resolve a setting that can be overridden from the environment: a
+/// non-empty `env_var` (read through `env`) wins over the config file value
+pub fn layered(
+    env: impl Fn(&str) -> Option<String>,
+    env_var: &'static str,
+    configured: Option<String>,
+) -> Option<(String, Origin)> {
+    flagged(None, env, env_var, configured)
+}
+
+/// `layered` with a command-line flag on top: the flag, then `env_var`,
+/// then the config file value
+pub fn flagged<T: From<String>>(
+    flag: Option<T>,
+    env: impl Fn(&str) -> Option<String>,
+    env_var: &'static str,
+    configured: Option<T>,
+) -> Option<(T, Origin)> {
+    if let Some(value) = flag {
+        return Some((value, Origin::Flag));
+    }
+    match env(env_var) {
+        Some(value) if !value.is_empty() => Some((T::from(value), Origin::Env(env_var))),
+        _ => configured.map(|value| (value, Origin::Config)),
+    }
+}
+
+/// System-wide location used by older installs
+pub const SYSTEM_SHELL_PATH: &str = "/etc/xdg/quickshell/noctalia-shell";
+/// Per-user location, relative to `$HOME`
+pub const USER_SHELL_PATH: &str = ".config/quickshell/noctalia-shell";
+
+pub fn user_shell_path() -> Option<PathBuf> {
+    env::var("HOME")
+        .ok()
+        .filter(|home| !home.is_empty())
+        .map(|home| PathBuf::from(home).join(USER_SHELL_PATH))
+}
+
+/// Locate an existing shell installation.
+///
+/// Precedence: the per-user path (`~/.config/quickshell/noctalia-shell`) wins
+/// over the system path (`/etc/xdg/quickshell/noctalia-shell`); `None` if
+/// neither exists. Install, update, systemd, run and ipc all resolve through
+/// this so they always agree on which copy is "the" installation.
+pub fn find_shell_path() -> Option<PathBuf> {
+    if let Some(user) = user_shell_path().filter(|p| p.exists()) {
+        return Some(user);
+    }
+    let system = PathBuf::from(SYSTEM_SHELL_PATH);
+    system.exists().then_some(system)
+}
+
+/// Where install/update should write: the existing installation if there is
+/// one (so it is overwritten in place rather than duplicated), otherwise the
+/// per-user path.
+pub fn shell_target_path() -> PathBuf {
+    find_shell_path()
+        .or_else(user_shell_path)
+        .expect("HOME environment variable not set")
+}
+
 fn check_shell_installed() -> bool {
-    // Check both possible installation locations
-    let old_path = PathBuf::from("/etc/xdg/quickshell/noctalia-shell");
-    let home = env::var("HOME").unwrap_or_else(|_| String::new());
-    let new_path = if !home.is_empty() {
-        PathBuf::from(home).join(".config/quickshell/noctalia-shell")
-    } else {
-        PathBuf::new()
-    };
-    
-    // Check if either location exists
-    old_path.exists() || (!new_path.as_os_str().is_empty() && new_path.exists())
+    find_shell_path().is_some()
 }
 
 pub fn config_path() -> PathBuf {
@@ -118,3 +478,191 @@ pub fn config_path() -> PathBuf {
     dirs.config_dir().join("cli.toml")
 }
 
+/// `--cache-dir`, set once at startup
+static CACHE_DIR_FLAG: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_cache_dir(dir: PathBuf) {
+    let _ = CACHE_DIR_FLAG.set(dir);
+}
+
+/// `cache_dir` and where it comes from: `--cache-dir`, then
+/// `NOCTALIA_CACHE_DIR`, then the per-user default
+pub fn cache_dir_origin() -> (PathBuf, Origin) {
+    if let Some(dir) = CACHE_DIR_FLAG.get() {
+        return (dir.clone(), Origin::Flag);
+    }
+    if let Some(dir) = env::var_os("NOCTALIA_CACHE_DIR").filter(|v| !v.is_empty()) {
+        return (PathBuf::from(dir), Origin::Env("NOCTALIA_CACHE_DIR"));
+    }
+    let dirs = ProjectDirs::from("dev", "noctalia", "noctalia").expect("failed to resolve cache dir");
+    (dirs.cache_dir().to_path_buf(), Origin::Default)
+}
+
+/// Per-user cache directory for state kept between runs (staged updates,
+/// the lock file, extraction scratch space)
+pub fn cache_dir() -> PathBuf {
+    cache_dir_origin().0
+}
+
+/// Scratch directory for extraction: `[cache] temp_dir`, then `TMPDIR`, then
+/// `<cache_dir>/tmp`. The default avoids small tmpfs mounts at /tmp.
+pub fn temp_dir() -> PathBuf {
+    CliConfig::load()
+        .ok()
+        .and_then(|(cfg, _)| cfg.cache.temp_dir)
+        .or_else(|| env::var_os("TMPDIR").filter(|v| !v.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(|| cache_dir().join("tmp"))
+}
+
+/// Components the CLI knows how to manage
+pub const KNOWN_COMPONENTS: &[&str] = &["shell"];
+
+/// Reject component names the CLI does not manage, listing the valid ones
+pub fn check_component(component: &str) -> CliResult {
+    if KNOWN_COMPONENTS.contains(&component) {
+        return Ok(());
+    }
+    Err(CliError::usage(format!("Unknown component '{}'", component))
+        .with_hint(format!("Known components: {}", KNOWN_COMPONENTS.join(", "))))
+}
+
+/// What to install: an `explicit` source from the command line (see
+/// `SourceSpec::from_flags`), then the latest of the default channel, else ask
+pub fn resolve_source(component: &str, explicit: Option<SourceSpec>, cfg: &CliConfig) -> CliResult<SourceSpec> {
+    if let Some(spec) = explicit {
+        return Ok(spec);
+    }
+
+    // A one-off --git or --release must not change the ongoing channel, so
+    // only an explicit default is used here, not the last installed source
+    if let Some(default) = cfg.get_default_source(component) {
+        return Ok(default.into());
+    }
+
+    prompt_and_persist_choice(component, cfg.get_component_source(component), ui::is_interactive()).map(SourceSpec::from)
+}
+
+/// Source for an update. Precedence: an explicit source flag, then the
+/// default set with `config set-source`, then the source of the existing
+/// installation; only if none of those exist is the user asked.
+pub fn resolve_update_source(component: &str, explicit: Option<SourceSpec>, cfg: &CliConfig) -> CliResult<SourceSpec> {
+    update_source(component, explicit, cfg, ui::is_interactive())
+}
+
+/// `resolve_update_source`, asking only if `interactive`
+fn update_source(component: &str, explicit: Option<SourceSpec>, cfg: &CliConfig, interactive: bool) -> CliResult<SourceSpec> {
+    if explicit.is_some() || cfg.get_default_source(component).is_some() {
+        return resolve_source(component, explicit, cfg);
+    }
+    match cfg.get_component_source(component) {
+        Some(installed) => Ok(installed.into()),
+        None => prompt_and_persist_choice(component, None, interactive).map(SourceSpec::from),
+    }
+}
+
+/// Ask for the default source of `component` and remember it. `current` is
+/// the source of the existing installation, preselected in the prompt.
+fn prompt_and_persist_choice(component: &str, current: Option<SourceKind>, interactive: bool) -> CliResult<SourceKind> {
+    let chosen = if ui::assume_yes() {
+        // --yes accepts the default choice
+        current.unwrap_or_default()
+    } else if !interactive {
+        return Err(CliError::usage(format!("Choosing a source for {} needs a terminal", component))
+            .with_hint("Pass --release or --git (or --yes to use the release)."));
+    } else {
+        let default = if current == Some(SourceKind::Git) { 1 } else { 0 };
+        let selection = ui::select(&format!("Choose source for {}", component), &["release", "git"], default)?;
+        match selection {
+            Some(1) => SourceKind::Git,
+            Some(_) => SourceKind::Release,
+            None => return Err(CliError::failure("No source chosen")),
+        }
+    };
+
+    let (mut cfg, path) = CliConfig::load().expect("load config");
+    cfg.set_default_source(component, Some(chosen));
+    cfg.save_or_warn(&path);
+    ui::info(&format!("Saved {} as the default source; change it with 'noctalia config set-source'", chosen));
+    Ok(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layered_prefers_env_over_config() {
+        const VAR: &str = "NOCTALIA_TEST_LAYERED";
+        let cases = [
+            (Some("env"), Some("config"), Some(("env", Origin::Env(VAR)))),
+            (Some("env"), None, Some(("env", Origin::Env(VAR)))),
+            (Some(""), Some("config"), Some(("config", Origin::Config))),
+            (None, Some("config"), Some(("config", Origin::Config))),
+            (None, None, None),
+        ];
+        for (env_value, configured, expected) in cases {
+            let env = |name: &str| env_value.filter(|_| name == VAR).map(str::to_string);
+            let got = layered(env, VAR, configured.map(str::to_string));
+            assert_eq!(got, expected.map(|(v, o)| (v.to_string(), o)), "env {:?}, config {:?}", env_value, configured);
+        }
+    }
+
+    #[test]
+    fn flagged_prefers_flag_then_env_then_config() {
+        const VAR: &str = "NOCTALIA_TEST_FLAGGED";
+        let cases = [
+            (Some("flag"), Some("env"), Some("config"), Some(("flag", Origin::Flag))),
+            (Some("flag"), None, None, Some(("flag", Origin::Flag))),
+            (None, Some("env"), Some("config"), Some(("env", Origin::Env(VAR)))),
+            (None, Some(""), Some("config"), Some(("config", Origin::Config))),
+            (None, None, Some("config"), Some(("config", Origin::Config))),
+            (None, None, None, None),
+        ];
+        for (flag, env_value, configured, expected) in cases {
+            let env = |name: &str| env_value.filter(|_| name == VAR).map(str::to_string);
+            let got = flagged(flag.map(PathBuf::from), env, VAR, configured.map(PathBuf::from));
+            assert_eq!(
+                got,
+                expected.map(|(v, o)| (PathBuf::from(v), o)),
+                "flag {:?}, env {:?}, config {:?}",
+                flag,
+                env_value,
+                configured
+            );
+        }
+    }
+
+    #[test]
+    fn quickshell_ppa_defaults_unless_configured() {
+        let ppa = |configured: Option<&str>| DependenciesConfig { ubuntu_ppa: configured.map(str::to_string) };
+        assert_eq!(ppa(None).quickshell_ppa(), Some(DEFAULT_UBUNTU_PPA));
+        assert_eq!(ppa(Some("me/quickshell")).quickshell_ppa(), Some("me/quickshell"));
+        assert_eq!(ppa(Some("")).quickshell_ppa(), None);
+    }
+
+    #[test]
+    fn update_source_prefers_flag_then_default_then_installed() {
+        use SourceKind::{Git, Release};
+        let cases = [
+            (Some(SourceSpec::LatestRelease), Some(Git), Some(Git), Some(SourceSpec::LatestRelease)),
+            (Some(SourceSpec::GitMain), Some(Release), Some(Release), Some(SourceSpec::GitMain)),
+            (None, Some(Git), Some(Release), Some(SourceSpec::GitMain)),
+            (None, Some(Release), Some(Git), Some(SourceSpec::LatestRelease)),
+            (None, None, Some(Git), Some(SourceSpec::GitMain)),
+            (None, None, Some(Release), Some(SourceSpec::LatestRelease)),
+            // Nothing to go on and no terminal to ask on
+            (None, None, None, None),
+        ];
+        for (flag, default, installed, expected) in cases {
+            let mut cfg = CliConfig::default();
+            if let Some(installed) = installed {
+                cfg.set_component_source("shell", installed);
+            }
+            if default.is_some() {
+                cfg.set_default_source("shell", default);
+            }
+            let got = update_source("shell", flag.clone(), &cfg, false).ok();
+            assert_eq!(got, expected, "flag {:?}, default {:?}, installed {:?}", flag, default, installed);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fmt, path::PathBuf};
+
+use super::SourceKind;
+
+/// Source options as given on the command line, before validation
+#[derive(Debug, Default)]
+pub struct SourceFlags {
+    pub git: bool,
+    pub release: bool,
+    /// `--tag` / `--to`: a specific release
+    pub tag: Option<String>,
+    /// `--branch`: the head of a git branch
+    pub branch: Option<String>,
+    /// `--commit`: a single git commit
+    pub commit: Option<String>,
+    /// `--from-url`: a tarball outside GitHub
+    pub url: Option<String>,
+    /// `--from-file`: a tarball on disk
+    pub file: Option<PathBuf>,
+}
+
+/// Exactly what install, update and download fetch. Only its coarse
+/// `SourceKind` is persisted in the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceSpec {
+    LatestRelease,
+    ReleaseTag(String),
+    GitMain,
+    GitBranch(String),
+    GitCommit(String),
+    LocalFile(PathBuf),
+    Url(String),
+}
+
+impl SourceSpec {
+    /// Check that `flags` name at most one source and turn them into a spec;
+    /// `None` when no source flag was given. Every combination of source
+    /// flags is validated here so commands agree on what conflicts.
+    pub fn from_flags(flags: SourceFlags) -> Result<Option<SourceSpec>, String> {
+        let SourceFlags { git, release, tag, branch, commit, url, file } = flags;
+        let any_github = git || release || tag.is_some() || branch.is_some() || commit.is_some();
+        match (url, file) {
+            (Some(_), Some(_)) => return Err("--from-url and --from-file cannot be combined".to_string()),
+            (Some(_), None) if any_github => {
+                return Err("--from-url cannot be combined with --git, --release, a tag, --branch or --commit".to_string());
+            }
+            (None, Some(_)) if any_github => {
+                return Err("--from-file cannot be combined with --git, --release, a tag, --branch or --commit".to_string());
+            }
+            (Some(url), None) => return Ok(Some(SourceSpec::Url(url))),
+            (None, Some(file)) => return Ok(Some(SourceSpec::LocalFile(file))),
+            (None, None) => {}
+        }
+        if git && release {
+            return Err("Both --git and --release provided; please specify only one.".to_string());
+        }
+
+        // --branch and --commit imply --git, a tag implies --release; either
+        // may still be given alongside the flag it implies
+        let git_ref = match (branch, commit) {
+            (Some(_), Some(_)) => return Err("--branch and --commit cannot be combined".to_string()),
+            (Some(branch), None) => Some(SourceSpec::GitBranch(branch)),
+            (None, Some(commit)) => Some(SourceSpec::GitCommit(commit)),
+            (None, None) => None,
+        };
+        match (git_ref, tag) {
+            (Some(_), Some(tag)) => Err(format!("Tag {} names a release; it cannot be combined with --branch or --commit", tag)),
+            (Some(_), None) if release => Err("--branch and --commit name git revisions; they cannot be combined with --release".to_string()),
+            (Some(spec), None) => Ok(Some(spec)),
+            (None, Some(tag)) if git => Err(format!("Tag {} names a release; it cannot be combined with --git", tag)),
+            (None, Some(tag)) => Ok(Some(SourceSpec::ReleaseTag(tag))),
+            (None, None) if git => Ok(Some(SourceSpec::GitMain)),
+            (None, None) if release => Ok(Some(SourceSpec::LatestRelease)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The channel recorded in the config for this source. A tarball from a
+    /// URL or a file has no channel of its own and counts as a release.
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            SourceSpec::GitMain | SourceSpec::GitBranch(_) | SourceSpec::GitCommit(_) => SourceKind::Git,
+            SourceSpec::LatestRelease | SourceSpec::ReleaseTag(_) | SourceSpec::LocalFile(_) | SourceSpec::Url(_) => {
+                SourceKind::Release
+            }
+        }
+    }
+
+    /// Whether this follows the newest version of its channel rather than
+    /// naming one revision or tarball
+    pub fn is_latest(&self) -> bool {
+        matches!(self, SourceSpec::LatestRelease | SourceSpec::GitMain)
+    }
+}
+
+/// The latest version of a persisted channel
+impl From<SourceKind> for SourceSpec {
+    fn from(kind: SourceKind) -> Self {
+        match kind {
+            SourceKind::Release => SourceSpec::LatestRelease,
+            SourceKind::Git => SourceSpec::GitMain,
+        }
+    }
+}
+
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceSpec::LatestRelease => write!(f, "release"),
+            SourceSpec::ReleaseTag(tag) => write!(f, "release {}", tag),
+            SourceSpec::GitMain => write!(f, "git"),
+            SourceSpec::GitBranch(branch) => write!(f, "git branch {}", branch),
+            SourceSpec::GitCommit(commit) => write!(f, "git commit {}", commit.get(..8).unwrap_or(commit)),
+            SourceSpec::LocalFile(path) => write!(f, "file {}", path.display()),
+            SourceSpec::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
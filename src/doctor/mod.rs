@@ -0,0 +1,128 @@
+use std::{env, process::Command};
+
+use crate::archive;
+use crate::config;
+use crate::error::{CliError, CliResult};
+use crate::install::systemd;
+use crate::output::{self, Check, CheckStatus as Status, DoctorReport};
+use crate::run::shell::is_noctalia_running;
+use crate::ui;
+
+fn command_exists(name: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+        .unwrap_or(false)
+}
+
+fn check_config() -> Check {
+    let path = config::config_path();
+    if !path.exists() {
+        return Check::new("config", Status::Pass, format!("{} not created yet (defaults in use)", path.display()));
+    }
+    match config::CliConfig::check_file(&path) {
+        Ok(()) => Check::new("config", Status::Pass, path.display().to_string()),
+        Err(e) => Check::new("config", Status::Fail, e),
+    }
+}
+
+fn check_shell() -> Check {
+    match config::find_shell_path() {
+        Some(path) if path.join(archive::ENTRY_POINT).exists() => {
+            Check::new("shell", Status::Pass, path.display().to_string())
+        }
+        Some(path) => Check::new(
+            "shell",
+            Status::Fail,
+            format!("{} has no {}; run 'noctalia install shell --reinstall'", path.display(), archive::ENTRY_POINT),
+        ),
+        None => Check::new("shell", Status::Fail, "not installed; run 'noctalia install shell'"),
+    }
+}
+
+fn check_quickshell() -> Check {
+    match Command::new("qs").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Check::new("quickshell", Status::Pass, version)
+        }
+        Ok(_) => Check::new("quickshell", Status::Fail, "'qs --version' failed"),
+        Err(_) => Check::new("quickshell", Status::Fail, "'qs' not found in PATH"),
+    }
+}
+
+fn check_dependencies() -> Check {
+    let missing: Vec<&str> = ["brightnessctl", "gpu-screen-recorder"]
+        .into_iter()
+        .filter(|name| !command_exists(name))
+        .collect();
+    if missing.is_empty() {
+        Check::new("dependencies", Status::Pass, "all optional tools found")
+    } else {
+        Check::new("dependencies", Status::Warn, format!("not in PATH: {}", missing.join(", ")))
+    }
+}
+
+fn check_running() -> Check {
+    if is_noctalia_running() {
+        Check::new("running", Status::Pass, "noctalia-shell is running")
+    } else {
+        Check::new("running", Status::Warn, "noctalia-shell is not running; start it with 'noctalia run'")
+    }
+}
+
+fn check_service() -> Check {
+    let unit = systemd::unit_path();
+    if unit.exists() {
+        Check::new("service", Status::Pass, unit.display().to_string())
+    } else {
+        Check::new("service", Status::Warn, "systemd unit not installed; see 'noctalia install systemd'")
+    }
+}
+
+/// Run every check; shared by the text and JSON renderers
+pub fn checks() -> Vec<Check> {
+    vec![
+        check_config(),
+        check_shell(),
+        check_quickshell(),
+        check_dependencies(),
+        check_running(),
+        check_service(),
+    ]
+}
+
+fn print_text(checks: &[Check]) {
+    ui::section("Noctalia Doctor");
+    for check in checks {
+        let line = format!("{}: {}", check.check, check.detail);
+        match check.status {
+            Status::Pass => ui::success(&line),
+            Status::Warn => ui::warn(&line),
+            Status::Fail => ui::error(&line),
+        }
+    }
+}
+
+fn print_json(checks: &[Check], healthy: bool) -> CliResult {
+    output::print(&DoctorReport { healthy, checks })
+}
+
+pub fn run() -> CliResult {
+    let json = ui::is_json();
+    let checks = checks();
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+
+    if json {
+        print_json(&checks, failed == 0)?;
+    } else {
+        print_text(&checks);
+    }
+
+    if failed > 0 {
+        return Err(CliError::failure(format!("{} check(s) failed", failed)));
+    }
+    if !json {
+        ui::success("No problems found");
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::{fs, path::Path};
+
+use crate::SourceKind;
+use crate::archive;
+use crate::config::{self, SourceSpec};
+use crate::error::{CliError, CliResult};
+use crate::http;
+use crate::install::shell::{Resolved, Tarball, resolve};
+use crate::interrupt;
+use crate::progress::{ProgressSink, TerminalSink};
+use crate::ui;
+
+/// Download the shell tarball for `spec` into `output`, by default the
+/// cache directory, and print its path and SHA-256. Neither the installation
+/// nor the CLI config is touched.
+pub fn run(spec: SourceSpec, output: Option<&Path>) -> CliResult {
+    ui::section("Download Noctalia Shell");
+    let _defer = interrupt::defer();
+    let mut sink = TerminalSink;
+
+    let Resolved { version, tarball, .. } = resolve(&spec, &mut sink)?;
+    let Tarball::Url(url) = tarball else {
+        return Err(CliError::usage(format!("{} is already on disk", spec)));
+    };
+    let name = match spec.kind() {
+        SourceKind::Git => version.get(..8).unwrap_or(&version).to_string(),
+        SourceKind::Release => version,
+    };
+
+    let dir = output.map(Path::to_path_buf).unwrap_or_else(config::cache_dir);
+    fs::create_dir_all(&dir).map_err(|e| CliError::failure(format!("Failed to create {}: {}", dir.display(), e)))?;
+    let file = dir.join(format!("noctalia-shell-{}.tar.gz", name));
+
+    let mut on_bytes = |done, total| sink.bytes(done, total);
+    ui::scope(&format!("Downloading {}", name), || http::download_with_progress(&url, &file, &mut on_bytes))
+        .map_err(|e| e.into_cli("Failed to download noctalia-shell"))?;
+    let checksum = archive::sha256_file(&file)
+        .map_err(|e| CliError::failure(format!("Failed to read {}: {}", file.display(), e)))?;
+
+    ui::success(&format!("Saved {}", file.display()));
+    ui::info(&format!("SHA-256: {}", checksum));
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Generic failure
+    Failure,
+    /// Invalid combination of arguments
+    Usage,
+    /// A requested remote resource (release, tag, commit) does not exist
+    NotFound,
+    /// The network was unreachable or the server returned an error
+    Network,
+    /// The GitHub API rate limit was exhausted
+    RateLimited,
+    /// Stopped by Ctrl-C (128 + SIGINT)
+    Interrupted,
+    /// Exit status forwarded from a child process (qs, package managers, ...)
+    Child(i32),
+}
+
+impl ExitCode {
+    pub fn code(self) -> i32 {
+        match self {
+            ExitCode::Failure => 1,
+            ExitCode::Usage => 2,
+            ExitCode::NotFound => 3,
+            ExitCode::Network => 4,
+            ExitCode::RateLimited => 5,
+            ExitCode::Interrupted => 130,
+            ExitCode::Child(code) => code,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CliError {
+    pub code: ExitCode,
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+impl CliError {
+    pub fn new(code: ExitCode, message: impl Into<String>) -> Self {
+        CliError { code, message: message.into(), hints: Vec::new() }
+    }
+
+    pub fn failure(message: impl Into<String>) -> Self {
+        Self::new(ExitCode::Failure, message)
+    }
+
+    pub fn usage(message: impl Into<String>) -> Self {
+        Self::new(ExitCode::Usage, message)
+    }
+
+    /// Fail with `code` without printing anything, for commands whose exit
+    /// status is the whole answer
+    pub fn silent(code: ExitCode) -> Self {
+        Self::new(code, String::new())
+    }
+
+    /// Attach a follow-up line shown after the error in text output
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+pub type CliResult<T = ()> = Result<T, CliError>;
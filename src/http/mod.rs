@@ -0,0 +1,765 @@
+use std::{
+    env, fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Once, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{StatusCode, blocking::Response};
+
+use crate::archive;
+use crate::config;
+use crate::error::{CliError, ExitCode};
+use crate::interrupt;
+use crate::ui;
+
+pub const DEFAULT_API_BASE: &str = "https://api.github.com";
+pub const DEFAULT_CODELOAD_BASE: &str = "https://codeload.github.com";
+const REPO: &str = "noctalia-dev/noctalia-shell";
+
+/// Redirects followed by a download before giving up
+const MAX_REDIRECTS: usize = 10;
+
+/// Default upper bound for a downloaded archive, overridable via `[http] max_download_mb`
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 200 * 1024 * 1024;
+
+/// Network options given on the command line, set once at startup
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// Maximum download speed in bytes per second
+    pub limit_rate: Option<u64>,
+    /// Connect over IPv4 only
+    pub prefer_ipv4: bool,
+    /// Accept any TLS certificate; a last resort behind intercepting proxies
+    pub insecure: bool,
+    /// PEM file of extra CA certificates to trust
+    pub cacert: Option<PathBuf>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+/// Certificates from the CA bundle, parsed once by `load_ca_bundle`
+static CA_CERTS: OnceLock<Vec<reqwest::Certificate>> = OnceLock::new();
+
+pub fn init(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+fn settings() -> Settings {
+    SETTINGS.get().cloned().unwrap_or_default()
+}
+
+/// Parse a rate such as `500k`, `2M` or `1048576` into bytes per second
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid rate '{}'", value))?;
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" | "K" => 1024,
+        "m" | "M" => 1024 * 1024,
+        "g" | "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown rate suffix '{}' (use k, M or G)", suffix)),
+    };
+    match number.saturating_mul(multiplier) {
+        0 => Err("rate must be greater than zero".to_string()),
+        rate => Ok(rate),
+    }
+}
+
+/// Parse a `--from-url` argument, accepting only http and https URLs
+pub fn parse_archive_url(value: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(value).map_err(|e| format!("invalid URL '{}': {}", value, e))?;
+    match url.scheme() {
+        "http" | "https" if url.host_str().is_some() => Ok(url.to_string()),
+        "http" | "https" => Err(format!("URL '{}' has no host", value)),
+        scheme => Err(format!("unsupported URL scheme '{}' (use http or https)", scheme)),
+    }
+}
+
+/// `host/path` of `url`, used to record where a custom archive came from
+pub fn url_label(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => format!("{}{}", parsed.host_str().unwrap_or_default(), parsed.path()),
+        Err(_) => url.to_string(),
+    }
+}
+
+/// `--limit-rate` as given on the command line, in bytes per second
+pub fn limit_rate_flag() -> Option<u64> {
+    settings().limit_rate
+}
+
+/// Download speed cap: `--limit-rate`, then `[http] limit_rate`
+fn limit_rate() -> Option<u64> {
+    settings().limit_rate.or_else(|| {
+        let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.http.limit_rate)?;
+        match parse_rate(&configured) {
+            Ok(rate) => Some(rate),
+            Err(e) => {
+                ui::warn(&format!("Ignoring [http] limit_rate: {}", e));
+                None
+            }
+        }
+    })
+}
+
+/// Reader that sleeps as needed to stay under `rate` bytes per second
+struct Throttled<R> {
+    inner: R,
+    rate: u64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Small chunks keep the pacing smooth (about ten reads per second)
+        let chunk = usize::try_from((self.rate / 10).max(1024)).unwrap_or(usize::MAX).min(buf.len());
+        let n = self.inner.read(&mut buf[..chunk])?;
+        self.transferred += n as u64;
+
+        let due = Duration::from_secs_f64(self.transferred as f64 / self.rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(ahead);
+        }
+        Ok(n)
+    }
+}
+
+/// Reader that reports the running byte count to `on_bytes`
+struct Counting<'a, R> {
+    inner: R,
+    done: u64,
+    total: Option<u64>,
+    on_bytes: &'a mut dyn FnMut(u64, Option<u64>),
+}
+
+impl<R: Read> Read for Counting<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if interrupt::requested() {
+            return Err(io::Error::other("interrupted"));
+        }
+        let n = self.inner.read(buf)?;
+        self.done += n as u64;
+        (self.on_bytes)(self.done, self.total);
+        Ok(n)
+    }
+}
+
+/// Why a request against GitHub (API or codeload) failed
+#[derive(Debug)]
+pub enum FetchError {
+    /// The request never got a response (DNS, connection, TLS, timeout)
+    Network(String),
+    /// The server answered with an unexpected status code
+    Http(u16),
+    /// The requested release, tag or commit does not exist
+    NotFound,
+    /// The response body was not what we expected
+    Decode(String),
+    /// The API rate limit is exhausted; `reset` is the Unix time it resets at
+    RateLimited { reset: Option<u64> },
+    /// The download is larger than `[http] max_download_mb`
+    TooLarge { limit: u64 },
+    /// The body ended before `Content-Length` bytes arrived
+    Truncated { expected: u64, received: u64 },
+    /// The server answered with an empty body
+    Empty { url: String },
+    /// The downloaded file is not a gzipped tarball; `got` describes what it was
+    NotArchive { got: String },
+    /// Writing the download to disk failed
+    Io(io::Error),
+    /// Ctrl-C was pressed during the download
+    Interrupted,
+}
+
+impl FetchError {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            FetchError::NotFound => ExitCode::NotFound,
+            FetchError::Network(_) | FetchError::Http(_) | FetchError::Truncated { .. } | FetchError::Empty { .. } => {
+                ExitCode::Network
+            }
+            FetchError::RateLimited { .. } => ExitCode::RateLimited,
+            FetchError::Interrupted => ExitCode::Interrupted,
+            FetchError::Decode(_) | FetchError::TooLarge { .. } | FetchError::NotArchive { .. } | FetchError::Io(_) => {
+                ExitCode::Failure
+            }
+        }
+    }
+
+    /// Turn the error into a `CliError` prefixed with `context`, with a hint
+    /// suited to the kind of failure
+    pub fn into_cli(self, context: &str) -> CliError {
+        let hint = match &self {
+            FetchError::Network(_) | FetchError::Truncated { .. } => {
+                Some("Check your network connection and try again.".to_string())
+            }
+            FetchError::Http(code) if *code >= 500 => Some("GitHub seems to be having trouble; try again later.".to_string()),
+            FetchError::NotFound => Some("Check the requested version, or [source] api_base if you use a mirror.".to_string()),
+            FetchError::RateLimited { reset } => Some(match reset.and_then(minutes_until) {
+                Some(minutes) => format!("The rate limit resets in about {} minute(s).", minutes),
+                None => "Wait a while before trying again.".to_string(),
+            }),
+            FetchError::TooLarge { .. } => Some("Raise [http] max_download_mb to allow larger downloads.".to_string()),
+            FetchError::NotArchive { .. } | FetchError::Empty { .. } => {
+                Some("A proxy or mirror may have answered with an error page; try again or check [source] codeload_base.".to_string())
+            }
+            _ => None,
+        };
+        let err = CliError::new(self.exit_code(), format!("{}: {}", context, self));
+        match hint {
+            Some(hint) => err.with_hint(hint),
+            None => err,
+        }
+    }
+}
+
+fn minutes_until(reset: u64) -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(reset.saturating_sub(now).div_ceil(60).max(1))
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Network(e) => write!(f, "network error: {}", e),
+            FetchError::Http(code) => write!(f, "server returned HTTP {}", code),
+            FetchError::NotFound => write!(f, "not found"),
+            FetchError::Decode(e) => write!(f, "unexpected response: {}", e),
+            FetchError::RateLimited { .. } => write!(f, "GitHub API rate limit exceeded"),
+            FetchError::TooLarge { limit } => {
+                write!(f, "download exceeds the maximum allowed size of {} MiB", limit / (1024 * 1024))
+            }
+            FetchError::Truncated { expected, received } => {
+                write!(f, "download was cut short: received {} of {} bytes", received, expected)
+            }
+            FetchError::Empty { url } => write!(f, "received empty response from {}", url),
+            FetchError::NotArchive { got } => write!(f, "downloaded file is not a valid archive (got {})", got),
+            FetchError::Io(e) => write!(f, "failed to write download: {}", e),
+            FetchError::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            FetchError::Decode(e.to_string())
+        } else {
+            FetchError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<io::Error> for FetchError {
+    fn from(e: io::Error) -> Self {
+        FetchError::Io(e)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ReleaseInfo {
+    pub tag_name: String,
+    #[serde(default)]
+    pub published_at: Option<String>,
+    #[serde(default)]
+    pub prerelease: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CommitInfo {
+    pub sha: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CommitDetails {
+    message: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CompareCommit {
+    sha: String,
+    commit: CommitDetails,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Comparison {
+    commits: Vec<CompareCommit>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GitObject {
+    sha: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GitRef {
+    object: GitObject,
+}
+
+fn base_url(
+    env: impl Fn(&str) -> Option<String>,
+    env_var: &'static str,
+    configured: Option<String>,
+    default: &str,
+) -> String {
+    config::layered(env, env_var, configured)
+        .map(|(value, _)| value)
+        .unwrap_or_else(|| default.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Build a GitHub API URL for the shell repository, honoring
+/// `NOCTALIA_API_BASE` and `[source] api_base` (in that order).
+pub fn api_url(path: &str) -> String {
+    let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.source.api_base);
+    let base = base_url(config::process_env, "NOCTALIA_API_BASE", configured, DEFAULT_API_BASE);
+    format!("{}/repos/{}/{}", base, REPO, path)
+}
+
+/// Build a codeload tarball URL for a git ref of the shell repository, honoring
+/// `NOCTALIA_CODELOAD_BASE` and `[source] codeload_base` (in that order).
+pub fn codeload_url(reference: &str) -> String {
+    let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.source.codeload_base);
+    let base = base_url(config::process_env, "NOCTALIA_CODELOAD_BASE", configured, DEFAULT_CODELOAD_BASE);
+    format!("{}/{}/tar.gz/{}", base, REPO, encode_ref(reference))
+}
+
+/// Percent-encode a git ref (tag, branch or SHA) for a URL path, keeping
+/// the `/` of names like `refs/tags/v1` or `release/1.0`
+pub fn encode_ref(reference: &str) -> String {
+    let mut encoded = String::with_capacity(reference.len());
+    for byte in reference.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Tarball of release `tag` from codeload (or its configured mirror), pinned
+/// to its `commit` when known so the files always match the commit recorded
+/// for them, even if the tag moves
+pub fn release_tarball_url(tag: &str, commit: Option<&str>) -> String {
+    match commit {
+        Some(commit) => codeload_url(commit),
+        None => codeload_url(&format!("refs/tags/{}", tag)),
+    }
+}
+
+/// Where install and update keep the tarballs they fetch. The directory
+/// belongs to the CLI, so pruning never touches files the user saved.
+pub fn downloads_dir() -> PathBuf {
+    let path = config::cache_dir().join("downloads");
+    if let Err(e) = fs::create_dir_all(&path) {
+        ui::warn(&format!("Could not create {} ({})", path.display(), e));
+    }
+    path
+}
+
+/// Tarballs install and update left in the downloads directory
+pub fn downloaded_archives() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(downloads_dir()) else { return Vec::new() };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            name.starts_with("noctalia-shell-") && name.ends_with(".tar.gz")
+        })
+        .collect()
+}
+
+/// Apply the `[cache]` policy to downloaded tarballs: keep everything when
+/// `keep_downloads` is set, otherwise remove those older than `max_age_days`.
+pub fn prune_downloads() {
+    let cache = config::CliConfig::load().map(|(cfg, _)| cfg.cache).unwrap_or_default();
+    if cache.keep_downloads {
+        return;
+    }
+
+    let max_age = Duration::from_secs(u64::from(cache.max_age_days) * 24 * 60 * 60);
+    for path in downloaded_archives() {
+        let age = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+        if age >= max_age {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+pub fn default_user_agent() -> String {
+    format!("noctalia-cli/{} (+https://github.com/noctalia-dev/noctalia)", env!("CARGO_PKG_VERSION"))
+}
+
+/// User agent sent with every request: `NOCTALIA_USER_AGENT`, then
+/// `[http] user_agent`, then the versioned default.
+pub fn user_agent() -> String {
+    let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.http.user_agent);
+    config::layered(config::process_env, "NOCTALIA_USER_AGENT", configured)
+        .map(|(ua, _)| ua)
+        .unwrap_or_else(default_user_agent)
+}
+
+/// CA bundle to trust in addition to the system store: `--cacert`, then
+/// `NOCTALIA_CA_BUNDLE`, then `[http] ca_bundle`
+pub fn ca_bundle() -> Option<(PathBuf, config::Origin)> {
+    let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.http.ca_bundle);
+    config::flagged(settings().cacert, config::process_env, "NOCTALIA_CA_BUNDLE", configured.map(PathBuf::from))
+}
+
+/// Parse the configured CA bundle up front so a bad file fails clearly
+/// instead of as a TLS error on the first request
+pub fn load_ca_bundle() -> Result<(), CliError> {
+    let Some((path, origin)) = ca_bundle() else {
+        return Ok(());
+    };
+    let invalid = |reason: String| {
+        CliError::usage(format!("Invalid CA bundle {} ({}): {}", path.display(), origin, reason))
+            .with_hint("Point --cacert, NOCTALIA_CA_BUNDLE or [http] ca_bundle at a PEM file of CA certificates.")
+    };
+    let pem = fs::read(&path).map_err(|e| invalid(e.to_string()))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| invalid(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid("no PEM certificates found".to_string()));
+    }
+    let _ = CA_CERTS.set(certs);
+    Ok(())
+}
+
+/// `--prefer-ipv4`, or a non-empty `NOCTALIA_PREFER_IPV4` other than `0`
+fn prefer_ipv4() -> bool {
+    settings().prefer_ipv4 || env::var("NOCTALIA_PREFER_IPV4").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Builder shared by every client. Binding to the IPv4 wildcard address
+/// makes connections to IPv6 addresses fail at once, so broken IPv6 routes
+/// no longer hang until the timeout.
+fn builder() -> reqwest::blocking::ClientBuilder {
+    let mut builder = reqwest::blocking::Client::builder().user_agent(user_agent());
+    if settings().insecure {
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| {
+            ui::warn_always("TLS certificate verification is DISABLED (--insecure); anyone on the network path can tamper with downloads");
+            ui::warn_always("Prefer trusting your proxy's CA in the system certificate store and dropping --insecure");
+        });
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    for cert in CA_CERTS.get().into_iter().flatten() {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    if prefer_ipv4() {
+        builder.local_address(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED))
+    } else {
+        builder
+    }
+}
+
+pub fn client() -> reqwest::blocking::Client {
+    builder().build().expect("failed to build http client")
+}
+
+/// Client that leaves redirects to the caller, see `follow_redirects`
+fn download_client() -> reqwest::blocking::Client {
+    builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("failed to build http client")
+}
+
+/// GET `url`, following redirects ourselves. Tarball URLs answer with a 302
+/// to a signed codeload URL, and proxies that strip automatic redirects
+/// otherwise leave us with an empty body.
+fn follow_redirects(url: &str) -> Result<Response, FetchError> {
+    let client = download_client();
+    let mut url = reqwest::Url::parse(url).map_err(|e| FetchError::Network(format!("invalid URL {}: {}", url, e)))?;
+    for _ in 0..=MAX_REDIRECTS {
+        let resp = client.get(url.clone()).send()?;
+        if !resp.status().is_redirection() {
+            return check_status(resp);
+        }
+        let location = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| FetchError::Decode(format!("HTTP {} redirect without a Location header", resp.status().as_u16())))?;
+        let next = resp.url().join(location).map_err(|e| FetchError::Decode(format!("bad redirect target '{}': {}", location, e)))?;
+        if ui::is_verbose() {
+            ui::info(&format!("Redirected to {}", next));
+        }
+        url = next;
+    }
+    Err(FetchError::Network(format!("too many redirects (more than {})", MAX_REDIRECTS)))
+}
+
+/// Refuse bodies that are clearly not an archive, such as an HTML error page
+/// served by a captive portal or proxy
+fn check_archive_type(resp: &Response) -> Result<(), FetchError> {
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if content_type.starts_with("text/") || content_type.contains("json") || content_type.contains("xml") {
+        return Err(FetchError::Decode(format!("expected a tarball but the server sent {}", content_type)));
+    }
+    Ok(())
+}
+
+/// Map non-success responses to the matching `FetchError`
+fn check_status(resp: Response) -> Result<Response, FetchError> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    let header = |name: &str| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let exhausted = header("x-ratelimit-remaining").as_deref() == Some("0");
+    if status == StatusCode::TOO_MANY_REQUESTS || (status == StatusCode::FORBIDDEN && exhausted) {
+        let reset = header("x-ratelimit-reset").and_then(|v| v.parse().ok());
+        return Err(FetchError::RateLimited { reset });
+    }
+    if status == StatusCode::NOT_FOUND {
+        return Err(FetchError::NotFound);
+    }
+    Err(FetchError::Http(status.as_u16()))
+}
+
+fn get(url: &str) -> Result<Response, FetchError> {
+    check_status(client().get(url).send()?)
+}
+
+fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, FetchError> {
+    Ok(get(url)?.json()?)
+}
+
+pub fn get_latest_commit_sha() -> Result<String, FetchError> {
+    get_commit_sha("main")
+}
+
+/// Full SHA of the commit a branch name or (abbreviated) SHA points at
+pub fn get_commit_sha(reference: &str) -> Result<String, FetchError> {
+    let commit: CommitInfo = get_json(&api_url(&format!("commits/{}", encode_ref(reference))))?;
+    Ok(commit.sha)
+}
+
+pub fn get_latest_release_info() -> Result<ReleaseInfo, FetchError> {
+    get_json(&api_url("releases/latest"))
+}
+
+pub fn get_release_by_tag(tag: &str) -> Result<ReleaseInfo, FetchError> {
+    get_json(&api_url(&format!("releases/tags/{}", encode_ref(tag))))
+}
+
+/// The `limit` most recent releases, newest first (GitHub caps this at 100)
+pub fn get_releases(limit: u32) -> Result<Vec<ReleaseInfo>, FetchError> {
+    get_json(&api_url(&format!("releases?per_page={}", limit)))
+}
+
+pub fn get_tag_commit_sha(tag: &str) -> Result<String, FetchError> {
+    let tag_ref: GitRef = get_json(&api_url(&format!("git/refs/tags/{}", encode_ref(tag))))?;
+
+    // Annotated tags point at a tag object which in turn points at the commit
+    if tag_ref.object.kind == "tag" {
+        let tag_object: GitRef = get_json(&api_url(&format!("git/tags/{}", tag_ref.object.sha)))?;
+        return Ok(tag_object.object.sha);
+    }
+    Ok(tag_ref.object.sha)
+}
+
+/// Commits in `base...head`, oldest first, as (sha, first line of the message)
+pub fn get_commits_between(base: &str, head: &str) -> Result<Vec<(String, String)>, FetchError> {
+    let comparison: Comparison = get_json(&api_url(&format!("compare/{}...{}", encode_ref(base), encode_ref(head))))?;
+    Ok(comparison
+        .commits
+        .into_iter()
+        .map(|c| {
+            let summary = c.commit.message.lines().next().unwrap_or_default().to_string();
+            (c.sha, summary)
+        })
+        .collect())
+}
+
+pub fn max_download_bytes() -> u64 {
+    config::CliConfig::load()
+        .ok()
+        .and_then(|(cfg, _)| cfg.http.max_download_mb)
+        .map(|mb| mb.saturating_mul(1024 * 1024))
+        .unwrap_or(DEFAULT_MAX_DOWNLOAD_BYTES)
+}
+
+/// Compare the bytes written against the `Content-Length` header, if any, to
+/// catch a connection that dropped mid-download
+fn check_length(expected: Option<u64>, received: u64) -> Result<(), FetchError> {
+    match expected {
+        Some(expected) if expected != received => Err(FetchError::Truncated { expected, received }),
+        _ => Ok(()),
+    }
+}
+
+/// Download `url` to `out`, enforcing the configured size limit
+pub fn download_to(url: &str, out: &Path) -> Result<(), FetchError> {
+    download_with_progress(url, out, &mut |_, _| {})
+}
+
+/// `download_to`, calling `on_bytes(done, total)` as the body arrives
+pub fn download_with_progress(
+    url: &str,
+    out: &Path,
+    on_bytes: &mut dyn FnMut(u64, Option<u64>),
+) -> Result<(), FetchError> {
+    let resp = follow_redirects(url)?;
+    check_archive_type(&resp)?;
+    let final_url = resp.url().to_string();
+    let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+    // Refuse early when the server already tells us the body is too large
+    let limit = max_download_bytes();
+    let expected = resp.content_length();
+    if expected.is_some_and(|len| len > limit) {
+        return Err(FetchError::TooLarge { limit });
+    }
+
+    // Stream to disk, reading at most one byte past the limit to detect overflow
+    let mut file = fs::File::create(out)?;
+    let mut body = Counting { inner: resp, done: 0, total: expected, on_bytes }.take(limit + 1);
+    let copied = match limit_rate() {
+        Some(rate) => {
+            let mut body = Throttled { inner: body, rate, started: Instant::now(), transferred: 0 };
+            io::copy(&mut body, &mut file)
+        }
+        None => io::copy(&mut body, &mut file),
+    };
+    let written = match copied {
+        Ok(written) => written,
+        Err(e) => {
+            // Never leave a partial archive behind to be mistaken for a complete one
+            let received = file.metadata().map(|m| m.len()).unwrap_or_default();
+            drop(file);
+            let _ = fs::remove_file(out);
+            if interrupt::requested() {
+                return Err(FetchError::Interrupted);
+            }
+            // Reading the body fails with the client's error inside; anything
+            // else came from writing the file
+            return Err(match e.get_ref().and_then(|inner| inner.downcast_ref::<reqwest::Error>()) {
+                Some(inner) => match (expected, inner.is_body() || inner.is_decode()) {
+                    (Some(expected), true) => FetchError::Truncated { expected, received },
+                    _ => FetchError::Network(inner.to_string()),
+                },
+                None => e.into(),
+            });
+        }
+    };
+    if written > limit {
+        drop(file);
+        let _ = fs::remove_file(out);
+        return Err(FetchError::TooLarge { limit });
+    }
+    drop(file);
+    if let Err(e) = check_length(expected, written) {
+        let _ = fs::remove_file(out);
+        return Err(e);
+    }
+    // A 200 with no body (or `Content-Length: 0`) passes the checks above
+    if written == 0 {
+        let _ = fs::remove_file(out);
+        return Err(FetchError::Empty { url: final_url });
+    }
+
+    // Catch HTML error pages and truncated bodies here rather than as a
+    // cryptic gzip/tar error during extraction
+    if let Err(got) = archive::check_magic(out) {
+        let _ = fs::remove_file(out);
+        let got = match content_type {
+            Some(content_type) => format!("{}, content-type {}", got, content_type),
+            None => got,
+        };
+        return Err(FetchError::NotArchive { got });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::TcpListener, thread};
+
+    use super::*;
+
+    /// Answer one request on a local port with `head` followed by `body`, then
+    /// hang up; returns the URL to fetch
+    fn serve_once(head: &str, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/noctalia-shell.tar.gz", listener.local_addr().unwrap());
+        let head = head.to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(body);
+        });
+        url
+    }
+
+    #[test]
+    fn short_read_removes_the_partial_file() {
+        let url = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/gzip\r\nContent-Length: 1000\r\nConnection: close\r\n\r\n",
+            &[0x1f, 0x8b, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("noctalia-shell.tar.gz");
+
+        let err = download_to(&url, &out).unwrap_err();
+        assert!(matches!(err, FetchError::Truncated { expected: 1000, received: 10 }), "{}", err);
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn empty_body_is_an_error() {
+        let url = serve_once("HTTP/1.1 200 OK\r\nContent-Type: application/gzip\r\nContent-Length: 0\r\n\r\n", b"");
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("noctalia-shell.tar.gz");
+
+        let err = download_to(&url, &out).unwrap_err();
+        assert!(matches!(err, FetchError::Empty { .. }), "{}", err);
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn base_url_layers_env_config_and_default() {
+        const VAR: &str = "NOCTALIA_TEST_BASE_URL";
+        let cases = [
+            (Some("https://env.example/"), Some("https://config.example"), "https://env.example"),
+            (None, Some("https://config.example/"), "https://config.example"),
+            (None, None, "https://default.example"),
+        ];
+        for (env_value, configured, expected) in cases {
+            let env = |name: &str| env_value.filter(|_| name == VAR).map(str::to_string);
+            assert_eq!(base_url(env, VAR, configured.map(str::to_string), "https://default.example"), expected);
+        }
+    }
+
+    #[test]
+    fn encode_ref_escapes_everything_but_path_safe_characters() {
+        assert_eq!(encode_ref("v1.2.3"), "v1.2.3");
+        assert_eq!(encode_ref("refs/tags/release/1.0"), "refs/tags/release/1.0");
+        assert_eq!(encode_ref("v1.0+build 2?#"), "v1.0%2Bbuild%202%3F%23");
+        assert_eq!(encode_ref("ü"), "%C3%BC");
+    }
+}
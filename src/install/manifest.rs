@@ -0,0 +1,67 @@
+//! `dependencies.toml`, shipped at the root of the shell repository, lists the
+//! packages the shell needs:
+//!
+//! ```toml
+//! [[dependency]]
+//! name = "quickshell"        # generic name, also the default package name
+//! fedora = ""                # per-distro package name; "" = not packaged there
+//!
+//! [[dependency]]
+//! name = "brightnessctl"
+//! ```
+//!
+//! Recognized distro keys are `arch`, `fedora`, `debian`, `ubuntu`, `gentoo` and
+//! `void`.
+//! Without an override, the CLI's built-in mapping is used for known packages
+//! and the generic name for everything else.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::archive;
+use crate::ui;
+
+pub const MANIFEST_NAME: &str = "dependencies.toml";
+
+#[derive(Debug, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    /// Package names keyed by distro (`arch`, `fedora`, ...)
+    #[serde(flatten)]
+    pub packages: HashMap<String, String>,
+}
+
+impl Dependency {
+    /// Package name override for `distro`; `Some(None)` means explicitly unavailable
+    pub fn package_for(&self, distro: &str) -> Option<Option<&str>> {
+        self.packages.get(distro).map(|pkg| Some(pkg.as_str()).filter(|p| !p.is_empty()))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    #[serde(default, rename = "dependency")]
+    pub dependencies: Vec<Dependency>,
+}
+
+/// Load the manifest from a downloaded shell tarball. Returns `None` (after a
+/// warning if it exists but is invalid) when the built-in list should be used.
+pub fn from_archive(archive_path: &Path) -> Option<Manifest> {
+    let content = match archive::read_file(archive_path, MANIFEST_NAME) {
+        Ok(Some(content)) => content,
+        Ok(None) => return None,
+        Err(e) => {
+            ui::warn(&format!("Could not read {} from the archive: {}", MANIFEST_NAME, e));
+            return None;
+        }
+    };
+    match toml::from_str::<Manifest>(&content) {
+        Ok(manifest) if !manifest.dependencies.is_empty() => Some(manifest),
+        Ok(_) => None,
+        Err(e) => {
+            ui::warn(&format!("Ignoring invalid {}: {}", MANIFEST_NAME, e));
+            None
+        }
+    }
+}
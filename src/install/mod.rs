@@ -1,3 +1,4 @@
+pub mod manifest;
 pub mod shell;
 pub mod systemd;
 
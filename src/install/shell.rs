@@ -1,275 +1,433 @@
-use std::{env, fs, path::PathBuf, process::Command};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use crate::SourceKind;
-use crate::config;
+use crate::archive;
+use crate::config::{self, SourceSpec};
+use crate::error::{CliError, CliResult};
+use crate::http::{self, codeload_url, get_commit_sha, get_latest_commit_sha, get_latest_release_info, get_tag_commit_sha};
+use crate::install::manifest::{self, Manifest};
+use crate::install::systemd;
+use crate::interrupt;
+use crate::output::{self, InstallResult};
+use crate::privilege::Escalation;
+use crate::progress::{Level, ProgressSink};
+use crate::run;
 use crate::ui;
+use crate::update::shell::display_version;
+
+/// How dependencies are installed
+#[derive(Debug, Default)]
+pub struct DependencyOptions {
+    /// Never enable the quickshell COPR on Fedora
+    pub no_copr: bool,
+    /// Arch: install quickshell from the official repos with pacman, never the AUR
+    pub prefer_system_quickshell: bool,
+    /// Skip probing which packages are installed and hand the whole list to
+    /// the package manager, which no-ops on installed ones
+    pub assume_missing: bool,
+    /// Skip detection and install packages for this distribution
+    pub distro: Option<Distribution>,
+}
 
-const REPO_API: &str = "https://api.github.com/repos/noctalia-dev/noctalia-shell";
-const REPO_CODELOAD_MAIN: &str = "https://codeload.github.com/noctalia-dev/noctalia-shell/tar.gz/refs/heads/main";
+/// Whether `generic_name` is installed according to `probe`; always false
+/// with `--assume-missing-deps`, without running the probe
+fn already_installed(opts: &DependencyOptions, generic_name: &str, probe: impl FnOnce() -> bool) -> bool {
+    if opts.assume_missing || !probe() {
+        return false;
+    }
+    ui::info(&format!("{} is already installed", generic_name));
+    true
+}
 
-fn target_root() -> PathBuf {
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    PathBuf::from(home).join(".config/quickshell/noctalia-shell")
+/// Where the tarball of a resolved source is
+pub(crate) enum Tarball {
+    Url(String),
+    File(PathBuf),
 }
 
-pub fn run(source: SourceKind) {
-    ui::section("Noctalia Shell");
-    ui::info(&format!("Source: {}", source));
-    let target = target_root();
-    ui::info(&format!("Installing into {}", target.display()));
+/// A source resolved to the version recorded for it and its tarball
+pub(crate) struct Resolved {
+    pub version: String,
+    pub commit: Option<String>,
+    pub tarball: Tarball,
+}
 
-    // Install dependencies first
-    ui::section("Installing Dependencies");
-    let required_packages = vec!["quickshell", "gpu-screen-recorder", "brightnessctl"];
-    match install_dependencies(&required_packages) {
-        Ok(()) => {
-            ui::success("All dependencies installed successfully");
-        }
+/// Commit of release `tag`, warning instead of failing when it is unknown
+fn tag_commit(tag: &str, sink: &mut dyn ProgressSink) -> Option<String> {
+    match get_tag_commit_sha(tag) {
+        Ok(sha) => Some(sha),
         Err(e) => {
-            ui::error(&format!("Failed to install dependencies: {}", e));
-            ui::section("Installation Aborted");
-            ui::error("Cannot proceed with shell installation until all dependencies are available.");
-            ui::info("Please install the missing packages manually and run the installation again.");
-            std::process::exit(1);
-        }
-    }
-
-    let version = match source {
-        SourceKind::Git => {
-            ui::step("Fetching latest commit from git main");
-            let commit_sha = match get_latest_commit_sha() {
-                Ok(sha) => sha,
-                Err(e) => {
-                    ui::error(&format!("Failed to fetch latest commit: {}", e));
-                    std::process::exit(1);
-                }
-            };
-            let display = if commit_sha.len() >= 8 { &commit_sha[..8] } else { commit_sha.as_str() };
-            ui::info(&format!("Latest commit: {}", display));
-            ui::step("Downloading (git main)");
-            if let Err(e) = download_and_extract_git_main() {
-                ui::error(&format!("Failed to install noctalia-shell (git): {}", e));
-                std::process::exit(1);
-            } else {
-                ui::info("Completed (git main)");
-            }
-            commit_sha
-        }
-        SourceKind::Release => {
-            ui::step("Fetching latest release");
-            let release_info = match get_latest_release_info() {
-                Ok(info) => info,
-                Err(e) => {
-                    ui::error(&format!("Failed to fetch latest release: {}", e));
-                    std::process::exit(1);
-                }
-            };
-            ui::info(&format!("Latest release: {}", release_info.tag_name));
-            ui::step("Downloading (latest release)");
-            if let Err(e) = download_and_extract_latest_release() {
-                ui::error(&format!("Failed to install noctalia-shell (release): {}", e));
-                std::process::exit(1);
-            } else {
-                ui::info("Completed (latest release)");
-            }
-            release_info.tag_name
+            sink.message(Level::Warn, &format!("Could not resolve commit for {}: {}", tag, e));
+            None
         }
-    };
-
-    let (mut cfg, path) = config::CliConfig::load().expect("load config");
-    cfg.set_component_source("shell", source);
-    cfg.set_installed("shell", true);
-    cfg.set_component_version("shell", version);
-    let _ = cfg.save(&path);
-    ui::success(&format!("Installed to {}", target_root().display()));
+    }
 }
 
-fn downloads_dir() -> PathBuf {
-    // Prefer $HOME/Downloads on Linux; create if missing
-    let home = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-    let path = PathBuf::from(home).join("Downloads");
-    if let Err(e) = fs::create_dir_all(&path) {
-        eprintln!("Warning: could not create Downloads dir ({}), falling back to /tmp", e);
-        return PathBuf::from("/tmp");
+/// Look up the version `spec` currently points at. Git sources are recorded
+/// by their full commit SHA, releases by tag.
+pub(crate) fn resolve(spec: &SourceSpec, sink: &mut dyn ProgressSink) -> CliResult<Resolved> {
+    let git = |sha: String| Resolved { tarball: Tarball::Url(codeload_url(&sha)), commit: Some(sha.clone()), version: sha };
+    match spec {
+        SourceSpec::GitMain => {
+            sink.phase("Fetching latest commit from git main");
+            let commit_sha = get_latest_commit_sha().map_err(|e| e.into_cli("Failed to fetch latest commit"))?;
+            sink.message(Level::Info, &format!("Latest: {}", display_version(SourceKind::Git, &commit_sha)));
+            Ok(git(commit_sha))
+        }
+        SourceSpec::GitBranch(branch) | SourceSpec::GitCommit(branch) => {
+            sink.phase(&format!("Fetching {}", spec));
+            let commit_sha = get_commit_sha(branch).map_err(|e| e.into_cli(&format!("Failed to fetch {}", spec)))?;
+            sink.message(Level::Info, &format!("Resolved to {}", display_version(SourceKind::Git, &commit_sha)));
+            Ok(git(commit_sha))
+        }
+        SourceSpec::LatestRelease => {
+            sink.phase("Fetching latest release");
+            let release_info = get_latest_release_info().map_err(|e| e.into_cli("Failed to fetch latest release"))?;
+            sink.message(Level::Info, &format!("Latest release: {}", release_info.tag_name));
+            let commit = tag_commit(&release_info.tag_name, sink);
+            let tarball = Tarball::Url(http::release_tarball_url(&release_info.tag_name, commit.as_deref()));
+            Ok(Resolved { version: release_info.tag_name, commit, tarball })
+        }
+        SourceSpec::ReleaseTag(tag) => {
+            sink.phase(&format!("Fetching release {}", tag));
+            let release_info = http::get_release_by_tag(tag).map_err(|e| e.into_cli(&format!("Failed to fetch release {}", tag)))?;
+            let commit = tag_commit(&release_info.tag_name, sink);
+            let tarball = Tarball::Url(http::release_tarball_url(&release_info.tag_name, commit.as_deref()));
+            Ok(Resolved { version: release_info.tag_name, commit, tarball })
+        }
+        SourceSpec::Url(url) => Ok(Resolved {
+            version: format!("url:{}", http::url_label(url)),
+            commit: None,
+            tarball: Tarball::Url(url.clone()),
+        }),
+        SourceSpec::LocalFile(path) => {
+            if !path.is_file() {
+                return Err(CliError::usage(format!("{} is not a file", path.display())));
+            }
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            Ok(Resolved { version: format!("file:{}", name), commit: None, tarball: Tarball::File(path.clone()) })
+        }
     }
-    path
 }
 
-fn http_client() -> reqwest::blocking::Client {
-    reqwest::blocking::Client::builder()
-        .user_agent("noctalia-cli (+https://github.com/noctalia-dev/noctalia)")
-        .build()
-        .expect("failed to build http client")
+/// What to do once the files are in place
+#[derive(Debug, Default)]
+pub struct AfterInstall {
+    /// Print the next steps for a new user
+    pub hints: bool,
+    /// Start the shell briefly to check that it runs
+    pub verify: bool,
 }
 
-fn download_git_main() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let resp = client.get(REPO_CODELOAD_MAIN).send()?;
-    if !resp.status().is_success() { return Err(format!("http {}", resp.status()).into()); }
-    let bytes = resp.bytes()?;
-    let out = downloads_dir().join("noctalia-shell-main.tar.gz");
-    fs::write(&out, &bytes)?;
-    Ok(out)
+/// Install the shell from `spec`. A URL or file source skips GitHub entirely.
+pub fn run(
+    spec: SourceSpec,
+    force: bool,
+    follow_symlink: bool,
+    reinstall: bool,
+    deps: DependencyOptions,
+    after: AfterInstall,
+    sink: &mut dyn ProgressSink,
+) -> CliResult {
+    let _defer = interrupt::defer();
+    let result = install(spec, force, follow_symlink, reinstall, deps, after, sink);
+    sink.finished(&result);
+    result
 }
 
-#[derive(serde::Deserialize)]
-struct ReleaseInfo { 
-    tag_name: String, 
-    tarball_url: String 
-}
+fn install(
+    spec: SourceSpec,
+    force: bool,
+    follow_symlink: bool,
+    reinstall: bool,
+    deps: DependencyOptions,
+    after: AfterInstall,
+    sink: &mut dyn ProgressSink,
+) -> CliResult {
+    ui::section("Noctalia Shell");
+    sink.message(Level::Info, &format!("Source: {}", spec));
+    let source = spec.kind();
+    let target = config::shell_target_path();
+    if !target.starts_with("/etc") {
+        archive::recover_partial(&target);
+    }
 
-#[derive(serde::Deserialize)]
-struct CommitInfo {
-    sha: String,
-}
+    // Resolve what we are about to install before touching anything. A
+    // reinstall keeps the recorded version when it came from the same channel.
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    let recorded = cfg
+        .get_component_version("shell")
+        .filter(|_| reinstall && spec.is_latest() && cfg.get_component_source("shell") == Some(source));
+    let Resolved { version, commit, tarball } = match recorded {
+        Some(version) => {
+            sink.phase(&format!("Reinstalling {}", display_version(source, &version)));
+            let commit = cfg.get_component_commit("shell");
+            let url = match source {
+                SourceKind::Git => codeload_url(&version),
+                SourceKind::Release => http::release_tarball_url(&version, commit.as_deref()),
+            };
+            Resolved { version: version.clone(), commit, tarball: Tarball::Url(url) }
+        }
+        None => resolve(&spec, sink)?,
+    };
 
-fn get_latest_commit_sha() -> Result<String, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let url = format!("{}/commits/main", REPO_API);
-    let commit: CommitInfo = client.get(url).send()?.json()?;
-    Ok(commit.sha)
-}
+    // Re-running install for the same version is a no-op unless forced
+    let already_installed = target.join(archive::ENTRY_POINT).exists()
+        && cfg.get_component_source("shell") == Some(source)
+        && cfg.get_component_version("shell").as_deref() == Some(version.as_str());
+    if already_installed && !force && !reinstall {
+        let display = display_version(source, &version);
+        sink.message(Level::Success, &format!("Already installed at {}; use --force to reinstall", display));
+        return print_result(source, version, commit, &target, false);
+    }
 
-fn get_latest_release_info() -> Result<ReleaseInfo, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let url = format!("{}/releases/latest", REPO_API);
-    let info: ReleaseInfo = client.get(url).send()?.json()?;
-    Ok(info)
-}
+    let install_target = archive::resolve_target(&target, follow_symlink)?;
+    archive::report_target(&target, &install_target);
+    archive::confirm_replace(&install_target, force)?;
+
+    if reinstall {
+        // Drop anything a previous interrupted install or staged update left behind
+        archive::clean_leftovers(&install_target);
+        let (mut cfg, path) = config::CliConfig::load().expect("load config");
+        if let Some(staged) = cfg.get_staged_update("shell") {
+            sink.message(Level::Info, &format!("Discarding staged update {}", staged.version));
+            let _ = fs::remove_file(&staged.archive);
+            cfg.set_staged_update("shell", None);
+            cfg.save_or_warn(&path);
+        }
+    }
 
-fn download_latest_release() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let info = get_latest_release_info()?;
-    let resp = client.get(info.tarball_url).send()?;
-    if !resp.status().is_success() { return Err(format!("http {}", resp.status()).into()); }
-    let bytes = resp.bytes()?;
-    let filename = format!("noctalia-shell-{}.tar.gz", info.tag_name);
-    let out = downloads_dir().join(filename);
-    fs::write(&out, &bytes)?;
-    Ok(out)
-}
+    // Download first so the dependency list can come from the shell's own manifest
+    let label = match &spec {
+        SourceSpec::Url(_) => "url".to_string(),
+        SourceSpec::GitMain => "git main".to_string(),
+        _ => spec.to_string(),
+    };
+    let archive = match &tarball {
+        Tarball::File(path) => path.clone(),
+        Tarball::Url(url) => {
+            let name = match &spec {
+                SourceSpec::Url(_) => "url",
+                SourceSpec::GitMain => "main",
+                _ if source == SourceKind::Git => version.get(..8).unwrap_or(&version),
+                _ => version.as_str(),
+            };
+            let archive = http::downloads_dir().join(format!("noctalia-shell-{}.tar.gz", name));
+            let mut on_bytes = |done, total| sink.bytes(done, total);
+            ui::scope(&format!("Downloading ({})", label), || http::download_with_progress(url, &archive, &mut on_bytes))
+                .map_err(|e| e.into_cli(&format!("Failed to download noctalia-shell ({})", source)))?;
+            archive
+        }
+    };
+    interrupt::check()?;
+    let manifest = manifest::from_archive(&archive);
+
+    // Find sudo, doas or pkexec before touching the package manager
+    let escalation = Escalation::detect()?;
+    if let Err(e) = ui::scope("Installing dependencies", || install_dependencies(manifest.as_ref(), &deps, &escalation)) {
+        ui::error(&format!("Failed to install dependencies: {}", e));
+        ui::section("Installation Aborted");
+        return Err(CliError::failure("Cannot proceed with shell installation until all dependencies are available.")
+            .with_hint("Please install the missing packages manually and run the installation again."));
+    }
 
-fn download_and_extract_git_main() -> Result<(), Box<dyn std::error::Error>> {
-    let archive = download_git_main()?;
-    extract(&archive)?;
-    // Remove the archive to leave only the folder
-    let _ = fs::remove_file(&archive);
-    Ok(())
-}
+    // Past this point the old installation is replaced; only start if we may finish
+    interrupt::check()?;
+    sink.phase("Extracting");
+    let installed = match &tarball {
+        Tarball::Url(url) => {
+            archive::install_with_retry(&archive, &install_target, || http::download_to(url, &archive).map_err(Into::into))
+        }
+        Tarball::File(_) => archive::install_into(&archive, &install_target),
+    };
+    installed.map_err(|e| CliError::failure(format!("Failed to install noctalia-shell ({}): {}", source, e)))?;
+    http::prune_downloads();
+    sink.message(Level::Info, &format!("Completed ({})", label));
 
-fn download_and_extract_latest_release() -> Result<(), Box<dyn std::error::Error>> {
-    let archive = download_latest_release()?;
-    extract(&archive)?;
-    // Remove the archive to leave only the folder
-    let _ = fs::remove_file(&archive);
-    Ok(())
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    cfg.set_component_source("shell", source);
+    cfg.set_installed("shell", true);
+    cfg.set_component_version("shell", version.clone());
+    cfg.set_component_commit("shell", commit.clone());
+    cfg.set_fingerprint("shell", archive::fingerprint(&install_target).ok());
+    cfg.save_or_warn(&path);
+    sink.message(Level::Success, &format!("Installed to {}", target.display()));
+    if after.verify {
+        ui::scope("Checking that noctalia-shell starts", run::shell::verify_starts)
+            .map_err(|e| e.with_hint("The files are installed; fix the problem above and try 'noctalia run'."))?;
+    }
+    if after.hints {
+        print_next_steps();
+    }
+    print_result(source, version, commit, &target, true)
 }
 
-fn extract(archive_path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let target = target_root();
-    
-    // Remove existing directory if it exists
-    if target.exists() {
-        fs::remove_dir_all(&target)?;
-    }
-    
-    // Create parent directories
-    if let Some(parent) = target.parent() {
-        fs::create_dir_all(parent)?;
+/// The install result as JSON under `--output json`; the text output already
+/// said everything otherwise
+fn print_result(source: SourceKind, version: String, commit: Option<String>, target: &Path, changed: bool) -> CliResult {
+    if !ui::is_json() {
+        return Ok(());
     }
-    
-    // Extract archive
-    let file = fs::File::open(archive_path)?;
-    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
-    archive.unpack(&target)?;
-    
-    // Move contents up one level (strip-components=1 equivalent)
-    let extracted_dir = target.join("noctalia-shell-main");
-    if extracted_dir.exists() {
-        // Move all contents from noctalia-shell-main to target
-        for entry in fs::read_dir(&extracted_dir)? {
-            let entry = entry?;
-            let dest = target.join(entry.file_name());
-            fs::rename(entry.path(), dest)?;
-        }
-        fs::remove_dir(&extracted_dir)?;
+    output::print(&InstallResult {
+        component: "shell".to_string(),
+        source: source.to_string(),
+        version,
+        commit,
+        path: target.display().to_string(),
+        changed,
+    })
+}
+
+/// Tell a new user how to launch the shell and start it on login
+fn print_next_steps() {
+    ui::blank();
+    ui::info("Next steps:");
+    ui::item("Start it now: noctalia run");
+    if !systemd::is_systemd_running() {
+        ui::item("Start it on login: add 'noctalia run' to your compositor's autostart");
+        ui::item("  (e.g. exec-once in Hyprland, spawn-at-startup in niri)");
+    } else if systemd::is_installed() {
+        ui::item("Restart the service: systemctl --user restart noctalia.service");
     } else {
-        // Try with release tag name pattern
-        let entries: Vec<_> = fs::read_dir(&target)?.collect();
-        if entries.len() == 1 {
-            if let Some(Ok(entry)) = entries.into_iter().next() {
-                let entry_path = entry.path();
-                if entry_path.is_dir() {
-                    // Move all contents from the single subdirectory to target
-                    for sub_entry in fs::read_dir(&entry_path)? {
-                        let sub_entry = sub_entry?;
-                        let dest = target.join(sub_entry.file_name());
-                        fs::rename(sub_entry.path(), dest)?;
-                    }
-                    fs::remove_dir(&entry_path)?;
-                }
-            }
-        }
+        ui::item("Start it on login: noctalia install systemd");
     }
-    
-    Ok(())
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Distribution {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Distribution {
     Arch,
     Fedora,
     Debian,
+    Ubuntu,
     Gentoo,
     Void,
+    #[value(skip)]
     Unknown,
 }
 
-fn detect_distribution() -> Distribution {
-    // Check /etc/os-release first (most reliable for modern distros)
-    if let Ok(content) = fs::read_to_string("/etc/os-release") {
-        let mut id_value: Option<String> = None;
-        let mut id_like_value: Option<String> = None;
-        
-        // Parse ID and ID_LIKE fields from os-release
-        for line in content.lines() {
-            if line.starts_with("ID=") {
-                let id = line.trim_start_matches("ID=").trim_matches('"').trim_matches('\'').to_string();
-                id_value = Some(id);
-            } else if line.starts_with("ID_LIKE=") {
-                let id_like = line.trim_start_matches("ID_LIKE=").trim_matches('"').trim_matches('\'').to_string();
-                id_like_value = Some(id_like);
-            }
+impl Distribution {
+    /// Key used for per-distro package names in `dependencies.toml`
+    fn manifest_key(self) -> &'static str {
+        match self {
+            Distribution::Arch => "arch",
+            Distribution::Fedora => "fedora",
+            Distribution::Debian => "debian",
+            Distribution::Ubuntu => "ubuntu",
+            Distribution::Gentoo => "gentoo",
+            Distribution::Void => "void",
+            Distribution::Unknown => "unknown",
         }
-        
-        // Check ID first
-        if let Some(id) = &id_value {
-            match id.as_str() {
-                // Arch and Arch-based distributions
-                "arch" | "archlinux" | "archarm" | "archcraft" | "cachyos" | "Nyarch" |"endeavouros" | "manjaro" | "manjaro-arm" | "arcolinux" | "artix" | "garuda" | "parabola" => return Distribution::Arch,
-                "void" => return Distribution::Void,
-                "fedora" | "nobara" => return Distribution::Fedora,
-                "debian" | "pikaos" => return Distribution::Debian,
-                "ubuntu" => return Distribution::Debian,
-                "gentoo" => return Distribution::Gentoo,
-                _ => {}
-            }
+    }
+}
+
+/// Distribution to use instead of detecting it: `--force-distro`, then the
+/// `NOCTALIA_DISTRO` environment variable
+pub fn distro_override(flag: Option<Distribution>) -> CliResult<Option<Distribution>> {
+    use clap::ValueEnum;
+    if flag.is_some() {
+        return Ok(flag);
+    }
+    match env::var("NOCTALIA_DISTRO") {
+        Ok(name) if !name.is_empty() => Distribution::from_str(&name, true).map(Some).map_err(|_| {
+            let known: Vec<String> = Distribution::value_variants()
+                .iter()
+                .filter_map(|d| d.to_possible_value().map(|v| v.get_name().to_string()))
+                .collect();
+            CliError::usage(format!("Unknown distribution '{}' in NOCTALIA_DISTRO", name))
+                .with_hint(format!("Known distributions: {}", known.join(", ")))
+        }),
+        _ => Ok(None),
+    }
+}
+
+/// Value of `key` in os-release `content`. Tolerates CRLF endings, spaces
+/// around `=`, comments, and both quote styles (with `\` escapes inside
+/// double quotes). The last assignment wins, as in a shell.
+fn os_release_value(content: &str, key: &str) -> Option<String> {
+    let mut found = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
         }
-        
-        // Check ID_LIKE for forks that don't have explicit ID matches
-        if let Some(id_like) = &id_like_value {
-            if id_like.contains("arch") {
-                return Distribution::Arch;
-            }
-            if id_like.contains("debian") || id_like.contains("ubuntu") {
-                return Distribution::Debian;
-            }
-            if id_like.contains("fedora") {
-                return Distribution::Fedora;
+        let Some((name, raw)) = line.split_once('=') else {
+            continue;
+        };
+        if name.trim() != key {
+            continue;
+        }
+        let raw = raw.trim();
+        let value = if let Some(rest) = raw.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = rest.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
             }
+            value
+        } else if let Some(rest) = raw.strip_prefix('\'') {
+            rest.split('\'').next().unwrap_or_default().to_string()
+        } else {
+            // Unquoted: the value ends at whitespace, dropping a trailing comment
+            raw.split_whitespace().next().unwrap_or_default().to_string()
+        };
+        found = Some(value);
+    }
+    found
+}
+
+/// Map an os-release file to a distribution via `ID`, then `ID_LIKE`
+fn distribution_from_os_release(content: &str) -> Option<Distribution> {
+    // Check ID first
+    if let Some(id) = os_release_value(content, "ID") {
+        match id.as_str() {
+            // Arch and Arch-based distributions
+            "arch" | "archlinux" | "archarm" | "archcraft" | "cachyos" | "Nyarch" |"endeavouros" | "manjaro" | "manjaro-arm" | "arcolinux" | "artix" | "garuda" | "parabola" => return Some(Distribution::Arch),
+            "void" => return Some(Distribution::Void),
+            "fedora" | "nobara" => return Some(Distribution::Fedora),
+            "debian" | "pikaos" => return Some(Distribution::Debian),
+            "ubuntu" => return Some(Distribution::Ubuntu),
+            "gentoo" => return Some(Distribution::Gentoo),
+            _ => {}
         }
     }
 
+    // Check ID_LIKE for forks that don't have explicit ID matches. It is a
+    // space-separated list, so compare whole words: "archcraft-like" is not arch.
+    let id_like = os_release_value(content, "ID_LIKE")?;
+    let families: Vec<&str> = id_like.split_whitespace().collect();
+    let has = |family: &str| families.contains(&family);
+    if has("arch") || has("archlinux") {
+        return Some(Distribution::Arch);
+    }
+    // Ubuntu derivatives (Pop!_OS, Linux Mint) list both, e.g. ID_LIKE="ubuntu debian"
+    if has("ubuntu") {
+        return Some(Distribution::Ubuntu);
+    }
+    if has("debian") {
+        return Some(Distribution::Debian);
+    }
+    if has("fedora") {
+        return Some(Distribution::Fedora);
+    }
+    None
+}
+
+fn detect_distribution() -> Distribution {
+    // os-release is the most reliable source on modern distros; the spec
+    // falls back to /usr/lib/os-release when /etc/os-release is missing
+    let os_release = fs::read_to_string("/etc/os-release").or_else(|_| fs::read_to_string("/usr/lib/os-release"));
+    if let Some(dist) = os_release.ok().as_deref().and_then(distribution_from_os_release) {
+        return dist;
+    }
+
     // Fallback to traditional detection methods
     if PathBuf::from("/etc/arch-release").exists() {
         return Distribution::Arch;
@@ -301,7 +459,7 @@ fn get_package_mapping(dist: Distribution) -> Vec<(&'static str, Option<&'static
             ("gpu-screen-recorder", Some("gpu-screen-recorder")),
             ("brightnessctl", Some("brightnessctl")),
         ],
-        Distribution::Debian => vec![
+        Distribution::Debian | Distribution::Ubuntu => vec![
             ("quickshell", None), // May need PPA or manual build
             ("gpu-screen-recorder", Some("gpu-screen-recorder")),
             ("brightnessctl", Some("brightnessctl")),
@@ -324,48 +482,208 @@ fn get_package_mapping(dist: Distribution) -> Vec<(&'static str, Option<&'static
     }
 }
 
-fn install_dependencies(packages: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
-    let dist = detect_distribution();
-    let package_map = get_package_mapping(dist);
+/// Package list for `dist`: the shell's manifest when it ships one, with the
+/// built-in mapping filling in names the manifest doesn't override
+fn resolve_packages(dist: Distribution, manifest: Option<&Manifest>) -> Vec<(String, Option<String>)> {
+    let builtin = get_package_mapping(dist);
+    let Some(manifest) = manifest else {
+        return builtin
+            .into_iter()
+            .map(|(generic, pkg)| (generic.to_string(), pkg.map(str::to_string)))
+            .collect();
+    };
+
+    manifest
+        .dependencies
+        .iter()
+        .map(|dep| {
+            let pkg = match dep.package_for(dist.manifest_key()) {
+                Some(pkg) => pkg.map(str::to_string),
+                None => match builtin.iter().find(|(generic, _)| *generic == dep.name) {
+                    Some((_, pkg)) => pkg.map(str::to_string),
+                    None if matches!(dist, Distribution::Unknown) => None,
+                    None => Some(dep.name.clone()),
+                },
+            };
+            (dep.name.clone(), pkg)
+        })
+        .collect()
+}
+
+/// What to do after the package manager failed
+enum FailureChoice {
+    Retry,
+    Skip,
+    Abort,
+}
+
+/// Ask how to continue after a failed package install. `--yes` retries once
+/// and then aborts; without a terminal we abort straight away. The caller
+/// reports the error on abort.
+fn on_dependency_failure(error: &dyn std::error::Error, retried: bool) -> FailureChoice {
+    if ui::assume_yes() {
+        if retried {
+            return FailureChoice::Abort;
+        }
+        ui::warn(&format!("Installing dependencies failed: {}", error));
+        ui::info("Retrying once (--yes)");
+        return FailureChoice::Retry;
+    }
+    if !ui::is_interactive() {
+        return FailureChoice::Abort;
+    }
+    ui::warn(&format!("Installing dependencies failed: {}", error));
+    let items = ["Retry", "Skip and install the shell anyway", "Abort"];
+    match ui::select("How do you want to continue?", &items, 0) {
+        Ok(Some(0)) => FailureChoice::Retry,
+        Ok(Some(1)) => FailureChoice::Skip,
+        _ => FailureChoice::Abort,
+    }
+}
+
+fn install_dependencies(
+    manifest: Option<&Manifest>,
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dist = match opts.distro {
+        Some(dist) => {
+            ui::info(&format!("Using {} packages (distribution forced)", dist.manifest_key()));
+            dist
+        }
+        None => detect_distribution(),
+    };
+    if manifest.is_some() {
+        ui::info(&format!("Using dependency list from {}", manifest::MANIFEST_NAME));
+    }
+    let owned = resolve_packages(dist, manifest);
+    let package_map: Vec<(&str, Option<&str>)> =
+        owned.iter().map(|(generic, pkg)| (generic.as_str(), pkg.as_deref())).collect();
+
+    // Package managers fail for transient reasons (mirrors, a held lock), so
+    // offer to re-run just this step instead of starting over
+    let mut retried = false;
+    loop {
+        let Err(e) = install_packages(dist, &package_map, opts, escalation) else {
+            return Ok(());
+        };
+        match on_dependency_failure(e.as_ref(), retried) {
+            FailureChoice::Retry => retried = true,
+            FailureChoice::Skip => {
+                ui::warn("Continuing without all dependencies; noctalia-shell may not start");
+                return Ok(());
+            }
+            FailureChoice::Abort => return Err(e),
+        }
+    }
+}
+
+/// Hand `to_install` to the package manager `cmd` (already carrying its
+/// subcommand and flags), bracketed by a one-line plan and an N/N summary.
+/// Under --quiet the package manager's stdout is dropped.
+fn install_with(manager: &str, mut cmd: Command, to_install: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let count = to_install.len();
+    ui::step(&format!("Installing {} package(s) with {}: {}", count, manager, to_install.join(", ")));
+    let stdout = if ui::is_quiet() { std::process::Stdio::null() } else { std::process::Stdio::inherit() };
+    let status = cmd
+        .args(to_install)
+        .stdin(std::process::Stdio::inherit())
+        .stdout(stdout)
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        return Err(format!("Failed to install packages with {}", manager).into());
+    }
+    ui::success(&format!("{}/{} installed", count, count));
+    Ok(())
+}
 
+fn install_packages(
+    dist: Distribution,
+    package_map: &[(&str, Option<&str>)],
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let packages: Vec<&str> = package_map.iter().map(|(generic, _)| *generic).collect();
     match dist {
-        Distribution::Arch => install_arch_packages(&package_map),
-        Distribution::Fedora => install_fedora_packages(&package_map),
-        Distribution::Debian => install_debian_packages(&package_map),
-        Distribution::Gentoo => install_gentoo_packages(&package_map),
-        Distribution::Void => install_void_packages(&package_map),
+        Distribution::Arch => install_arch_packages(package_map, opts, escalation),
+        Distribution::Fedora => install_fedora_packages(package_map, opts, escalation),
+        Distribution::Debian => install_debian_packages(package_map, None, opts, escalation),
+        Distribution::Ubuntu => {
+            let (cfg, _path) = config::CliConfig::load().expect("load config");
+            install_debian_packages(package_map, Some(cfg.dependencies.quickshell_ppa()), opts, escalation)
+        }
+        Distribution::Gentoo => install_gentoo_packages(package_map, opts, escalation),
+        Distribution::Void => install_void_packages(package_map, opts, escalation),
         Distribution::Unknown => {
-            ui::error("Unknown Linux distribution detected.");
-            list_required_packages(packages);
+            ui::warn("Unknown Linux distribution detected.");
+            list_required_packages(&packages);
             Err("Cannot determine package manager for unknown distribution".into())
         }
     }
 }
 
-fn install_arch_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box<dyn std::error::Error>> {
-    // Check for AUR helpers
-    let aur_helper = if Command::new("yay").arg("--version").output().is_ok() {
-        Some("yay")
-    } else if Command::new("paru").arg("--version").output().is_ok() {
-        Some("paru")
-    } else {
-        None
+/// Oldest yay release known to handle `-S --needed --noconfirm` correctly
+const MIN_YAY_VERSION: [u64; 3] = [11, 0, 0];
+/// Oldest paru release known to handle `-S --needed --noconfirm` correctly
+const MIN_PARU_VERSION: [u64; 3] = [1, 11, 0];
+
+/// Version from `yay --version` / `paru --version` output such as
+/// `yay v12.3.5 - libalpm v15.0.0`
+fn parse_helper_version(output: &str) -> Option<Vec<u64>> {
+    let digits = output
+        .split_whitespace()
+        .filter_map(|t| t.strip_prefix('v'))
+        .find(|v| v.starts_with(|c: char| c.is_ascii_digit()))?;
+    let version = digits
+        .split(|c: char| !c.is_ascii_digit())
+        .take(3)
+        .map_while(|part| part.parse().ok())
+        .collect::<Vec<u64>>();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Warn when `helper` is older than the minimum we know works
+fn check_helper_version(helper: &str, version_output: &str) {
+    let minimum: &[u64] = match helper {
+        "yay" => &MIN_YAY_VERSION,
+        "paru" => &MIN_PARU_VERSION,
+        _ => return,
+    };
+    let Some(version) = parse_helper_version(version_output) else {
+        return;
     };
+    if version.as_slice() < minimum {
+        let show = |v: &[u64]| v.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+        ui::warn(&format!(
+            "{} {} is older than {}, which may mishandle --noconfirm; consider upgrading it",
+            helper,
+            show(&version),
+            show(minimum)
+        ));
+    }
+}
+
+fn install_arch_packages(
+    package_map: &[(&str, Option<&str>)],
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Check for AUR helpers
+    let aur_helper = ["yay", "paru"].into_iter().find_map(|helper| {
+        let output = Command::new(helper).arg("--version").output().ok()?;
+        check_helper_version(helper, &String::from_utf8_lossy(&output.stdout));
+        Some(helper)
+    });
 
     let mut to_install = Vec::new();
     let mut missing = Vec::new();
 
     for (generic_name, arch_name) in package_map {
         if let Some(pkg) = arch_name {
-            // Check if already installed
-            let output = Command::new("pacman")
-                .args(["-Q", pkg])
-                .output();
-            if let Ok(output) = output {
-                if output.status.success() {
-                    ui::info(&format!("{} is already installed", generic_name));
-                    continue;
-                }
+            let probe = || Command::new("pacman").args(["-Q", pkg]).output().is_ok_and(|o| o.status.success());
+            if already_installed(opts, generic_name, probe) {
+                continue;
             }
             to_install.push(*pkg);
         } else {
@@ -381,6 +699,30 @@ fn install_arch_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box
         return Err("Some required packages are not available in repositories".into());
     }
 
+    // Install quickshell from the official repos instead of through the AUR helper
+    if opts.prefer_system_quickshell && let Some(pos) = to_install.iter().position(|p| *p == "quickshell") {
+        let in_repos = Command::new("pacman")
+            .args(["-Si", "quickshell"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if !in_repos {
+            return Err("quickshell is not available in the official Arch repositories (--prefer-system-quickshell)".into());
+        }
+
+        ui::step("Installing quickshell with pacman");
+        let status = escalation.command("pacman")
+            .args(["-S", "--needed", "--noconfirm", "quickshell"])
+            .stdin(std::process::Stdio::inherit())
+            .stdout(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .status()?;
+        if !status.success() {
+            return Err("Failed to install quickshell with pacman".into());
+        }
+        to_install.remove(pos);
+    }
+
     if to_install.is_empty() {
         ui::success("All packages are already installed");
         return Ok(());
@@ -388,28 +730,17 @@ fn install_arch_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box
 
     match aur_helper {
         Some(helper) => {
-            ui::info(&format!("Using {} to install packages", helper));
-            ui::step(&format!("Installing {} package(s)", to_install.len()));
-            let mut args = vec!["-S", "--noconfirm"];
-            args.extend(to_install.iter().map(|s| *s));
-            
-            let status = Command::new(helper)
-                .args(&args)
-                .stdin(std::process::Stdio::inherit())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .status()?;
-            
-            if !status.success() {
-                return Err("Failed to install packages".into());
-            }
-            ui::success("Packages installed successfully");
+            // --needed keeps already installed packages from being reinstalled
+            let mut cmd = Command::new(helper);
+            // yay and paru elevate pacman themselves, with sudo unless told otherwise
+            cmd.args(["-S", "--needed", "--noconfirm"]).args(escalation.aur_helper_args());
+            install_with(helper, cmd, &to_install)?;
         }
         None => {
             ui::error("No AUR helper found (yay/paru). Please install one of the following:");
             ui::info("  yay: https://github.com/Jguer/yay");
             ui::info("  paru: https://github.com/Morganamilo/paru");
-            ui::info("");
+            ui::blank();
             ui::info("Then install the required packages manually:");
             let pkg_list = to_install.join(" ");
             ui::info(&format!("  yay -S {}", pkg_list));
@@ -420,21 +751,19 @@ fn install_arch_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box
     Ok(())
 }
 
-fn install_fedora_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box<dyn std::error::Error>> {
+fn install_fedora_packages(
+    package_map: &[(&str, Option<&str>)],
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut to_install = Vec::new();
     let mut missing = Vec::new();
 
     for (generic_name, fedora_name) in package_map {
         if let Some(pkg) = fedora_name {
-            // Check if already installed
-            let output = Command::new("rpm")
-                .args(["-q", pkg])
-                .output();
-            if let Ok(output) = output {
-                if output.status.success() {
-                    ui::info(&format!("{} is already installed", generic_name));
-                    continue;
-                }
+            let probe = || Command::new("rpm").args(["-q", pkg]).output().is_ok_and(|o| o.status.success());
+            if already_installed(opts, generic_name, probe) {
+                continue;
             }
             to_install.push(*pkg);
         } else {
@@ -447,17 +776,18 @@ fn install_fedora_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
         ui::info("quickshell is not available in standard Fedora repositories.");
         ui::info("It can be installed from the COPR repository: errornointernet/quickshell");
         
-        use dialoguer::{theme::ColorfulTheme, Confirm};
-        let theme = ColorfulTheme::default();
-        let should_enable = Confirm::with_theme(&theme)
-            .with_prompt("Would you like to enable the COPR repository errornointernet/quickshell?")
-            .interact()
-            .unwrap_or(false);
+        let should_enable = if opts.no_copr {
+            ui::info("Not enabling COPR (--no-copr)");
+            false
+        } else {
+            ui::confirm("Would you like to enable the COPR repository errornointernet/quickshell?", false)
+                .map_err(|e| format!("{} (pass --yes to enable it or --no-copr to skip it)", e))?
+        };
 
         if should_enable {
             ui::step("Enabling COPR repository errornointernet/quickshell");
-            let status = Command::new("sudo")
-                .args(["dnf", "copr", "enable", "-y", "errornointernet/quickshell"])
+            let status = escalation.command("dnf")
+                .args(["copr", "enable", "-y", "errornointernet/quickshell"])
                 .stdin(std::process::Stdio::inherit())
                 .stdout(std::process::Stdio::inherit())
                 .stderr(std::process::Stdio::inherit())
@@ -472,8 +802,11 @@ fn install_fedora_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
             missing.retain(|&x| x != "quickshell");
             to_install.push("quickshell");
         } else {
-            ui::info("Skipping COPR repository setup. quickshell will not be installed.");
-            ui::info("You can enable it manually later with: sudo dnf copr enable errornointernet/quickshell");
+            ui::warn("Skipping COPR repository setup. quickshell will not be installed.");
+            ui::info(&format!(
+                "You can enable it manually later with: {}",
+                escalation.shown("dnf copr enable errornointernet/quickshell")
+            ));
         }
     }
 
@@ -491,44 +824,42 @@ fn install_fedora_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
         return Ok(());
     }
 
-    ui::step(&format!("Installing {} package(s) with dnf", to_install.len()));
-    let mut args = vec!["install", "-y"];
-    args.extend(to_install.iter().map(|s| *s));
-
-    let status = Command::new("sudo")
-        .arg("dnf")
-        .args(&args)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
+    let mut cmd = escalation.command("dnf");
+    cmd.args(["install", "-y"]);
+    install_with("dnf", cmd, &to_install)
+}
 
-    if !status.success() {
-        return Err("Failed to install packages with dnf".into());
+/// Add `ppa` (`owner/name` or `ppa:owner/name`) and refresh the package lists
+fn add_ubuntu_ppa(ppa: &str, escalation: &Escalation) -> Result<(), Box<dyn std::error::Error>> {
+    let ppa = if ppa.starts_with("ppa:") { ppa.to_string() } else { format!("ppa:{}", ppa) };
+    ui::step(&format!("Adding {}", ppa));
+    for args in [["add-apt-repository", "-y", ppa.as_str()].as_slice(), &["apt", "update"]] {
+        escalation.run(args)?;
     }
-
-    ui::success("Packages installed successfully");
+    ui::success(&format!("{} added", ppa));
     Ok(())
 }
 
-fn install_debian_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box<dyn std::error::Error>> {
+/// `ubuntu` is `Some` on Ubuntu, carrying the quickshell PPA unless disabled
+fn install_debian_packages(
+    package_map: &[(&str, Option<&str>)],
+    ubuntu: Option<Option<&str>>,
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut to_install = Vec::new();
     let mut missing = Vec::new();
 
     for (generic_name, debian_name) in package_map {
         if let Some(pkg) = debian_name {
-            // Check if already installed
-            let output = Command::new("dpkg")
-                .args(["-l", pkg])
-                .output();
-            if let Ok(output) = output {
-                if output.status.success() {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    if stdout.contains("ii") {
-                        ui::info(&format!("{} is already installed", generic_name));
-                        continue;
-                    }
-                }
+            let probe = || {
+                Command::new("dpkg")
+                    .args(["-l", pkg])
+                    .output()
+                    .is_ok_and(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).contains("ii"))
+            };
+            if already_installed(opts, generic_name, probe) {
+                continue;
             }
             to_install.push(*pkg);
         } else {
@@ -536,6 +867,27 @@ fn install_debian_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
         }
     }
 
+    // On Ubuntu, quickshell can come from a PPA
+    if let Some(ppa) = ubuntu
+        && missing.contains(&"quickshell")
+    {
+        ui::info("quickshell is not available in the standard Ubuntu repositories.");
+        match ppa {
+            Some(ppa) => {
+                let prompt = format!("Would you like to add the PPA {} for quickshell?", ppa);
+                let should_add = ui::confirm(&prompt, false).map_err(|e| format!("{} (pass --yes to add it)", e))?;
+                if should_add {
+                    add_ubuntu_ppa(ppa, escalation)?;
+                    missing.retain(|&x| x != "quickshell");
+                    to_install.push("quickshell");
+                } else {
+                    ui::warn("Skipping PPA setup. quickshell will not be installed.");
+                }
+            }
+            None => ui::info("No PPA is offered ([dependencies] ubuntu_ppa is empty in the CLI config)."),
+        }
+    }
+
     if !missing.is_empty() {
         ui::error("The following packages are not available in Debian/Ubuntu repositories:");
         for pkg in &missing {
@@ -550,41 +902,24 @@ fn install_debian_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
         return Ok(());
     }
 
-    ui::step(&format!("Installing {} package(s) with apt", to_install.len()));
-    let mut args = vec!["install", "-y"];
-    args.extend(to_install.iter().map(|s| *s));
-
-    let status = Command::new("sudo")
-        .arg("apt")
-        .args(&args)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
-
-    if !status.success() {
-        return Err("Failed to install packages with apt".into());
-    }
-
-    ui::success("Packages installed successfully");
-    Ok(())
+    let mut cmd = escalation.command("apt");
+    cmd.args(["install", "-y"]);
+    install_with("apt", cmd, &to_install)
 }
 
-fn install_gentoo_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box<dyn std::error::Error>> {
+fn install_gentoo_packages(
+    package_map: &[(&str, Option<&str>)],
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut to_install = Vec::new();
     let mut missing = Vec::new();
 
     for (generic_name, gentoo_name) in package_map {
         if let Some(pkg) = gentoo_name {
-            // Check if already installed
-            let output = Command::new("equery")
-                .args(["list", pkg])
-                .output();
-            if let Ok(output) = output {
-                if output.status.success() {
-                    ui::info(&format!("{} is already installed", generic_name));
-                    continue;
-                }
+            let probe = || Command::new("equery").args(["list", pkg]).output().is_ok_and(|o| o.status.success());
+            if already_installed(opts, generic_name, probe) {
+                continue;
             }
             to_install.push(*pkg);
         } else {
@@ -606,41 +941,24 @@ fn install_gentoo_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), B
         return Ok(());
     }
 
-    ui::step(&format!("Installing {} package(s) with emerge", to_install.len()));
-    let mut args = vec!["-av"];
-    args.extend(to_install.iter().map(|s| *s));
-
-    let status = Command::new("sudo")
-        .arg("emerge")
-        .args(&args)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
-
-    if !status.success() {
-        return Err("Failed to install packages with emerge".into());
-    }
-
-    ui::success("Packages installed successfully");
-    Ok(())
+    let mut cmd = escalation.command("emerge");
+    cmd.args(["-av", "--noreplace"]);
+    install_with("emerge", cmd, &to_install)
 }
 
-fn install_void_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box<dyn std::error::Error>> {
+fn install_void_packages(
+    package_map: &[(&str, Option<&str>)],
+    opts: &DependencyOptions,
+    escalation: &Escalation,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut to_install = Vec::new();
     let mut missing = Vec::new();
 
     for (generic_name, void_name) in package_map {
         if let Some(pkg) = void_name {
-            // Check if already installed
-            let output = Command::new("xbps-query")
-                .arg(pkg)
-                .output();
-            if let Ok(output) = output {
-                if output.status.success() {
-                    ui::info(&format!("{} is already installed", generic_name));
-                    continue;
-                }
+            let probe = || Command::new("xbps-query").arg(pkg).output().is_ok_and(|o| o.status.success());
+            if already_installed(opts, generic_name, probe) {
+                continue;
             }
             to_install.push(*pkg);
         } else {
@@ -662,24 +980,9 @@ fn install_void_packages(package_map: &[(&str, Option<&str>)]) -> Result<(), Box
         return Ok(());
     }
 
-    ui::step(&format!("Installing {} package(s) with xbps-install", to_install.len()));
-    let mut args = vec!["-S", "-y"];
-    args.extend(to_install.iter().map(|s| *s));
-
-    let status = Command::new("sudo")
-        .arg("xbps-install")
-        .args(&args)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
-
-    if !status.success() {
-        return Err("Failed to install packages with xbps-install".into());
-    }
-
-    ui::success("Packages installed successfully");
-    Ok(())
+    let mut cmd = escalation.command("xbps-install");
+    cmd.args(["-S", "-y"]);
+    install_with("xbps-install", cmd, &to_install)
 }
 
 fn list_required_packages(packages: &[&str]) {
@@ -687,7 +990,64 @@ fn list_required_packages(packages: &[&str]) {
     for pkg in packages {
         ui::info(&format!("  - {}", pkg));
     }
-    ui::info("");
+    ui::blank();
     ui::info("Please install these packages manually using your distribution's package manager.");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARCH: &str = "NAME=\"Arch Linux\"\nPRETTY_NAME=\"Arch Linux\"\nID=arch\nBUILD_ID=rolling\nANSI_COLOR=\"38;2;23;147;209\"\nHOME_URL=\"https://archlinux.org/\"\nLOGO=archlinux-logo\n";
+    const FEDORA: &str = "NAME=\"Fedora Linux\"\nVERSION=\"40 (Workstation Edition)\"\nID=fedora\nVERSION_ID=40\nVERSION_CODENAME=\"\"\nPLATFORM_ID=\"platform:f40\"\nPRETTY_NAME=\"Fedora Linux 40 (Workstation Edition)\"\n";
+    const DEBIAN: &str = "PRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\nNAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nVERSION=\"12 (bookworm)\"\nVERSION_CODENAME=bookworm\nID=debian\nHOME_URL=\"https://www.debian.org/\"\n";
+    const UBUNTU: &str = "PRETTY_NAME=\"Ubuntu 24.04.1 LTS\"\nNAME=\"Ubuntu\"\nVERSION_ID=\"24.04\"\nVERSION=\"24.04.1 LTS (Noble Numbat)\"\nVERSION_CODENAME=noble\nID=ubuntu\nID_LIKE=debian\nUBUNTU_CODENAME=noble\n";
+    const GENTOO: &str = "NAME=Gentoo\nID=gentoo\nPRETTY_NAME=\"Gentoo Linux\"\nANSI_COLOR=\"1;32\"\nHOME_URL=\"https://www.gentoo.org/\"\n";
+    const VOID: &str = "NAME=\"Void\"\nID=\"void\"\nPRETTY_NAME=\"Void Linux\"\nHOME_URL=\"https://voidlinux.org/\"\nDOCUMENTATION_URL=\"https://docs.voidlinux.org/\"\nLOGO=\"void-logo\"\n";
+    const ENDEAVOUROS: &str = "NAME='EndeavourOS'\nPRETTY_NAME='EndeavourOS'\nID='endeavouros'\nID_LIKE='arch'\nBUILD_ID=rolling\n";
+    const CACHYOS: &str = "NAME=\"CachyOS Linux\"\nPRETTY_NAME=\"CachyOS\"\nID=cachyos\nID_LIKE=arch\nBUILD_ID=rolling\nANSI_COLOR=\"38;2;23;147;209\"\nHOME_URL=\"https://cachyos.org/\"\nLOGO=cachyos\n";
+    const POP_OS: &str = "NAME=\"Pop!_OS\"\nVERSION=\"22.04 LTS\"\nID=pop\nID_LIKE=\"ubuntu debian\"\nPRETTY_NAME=\"Pop!_OS 22.04 LTS\"\nVERSION_ID=\"22.04\"\nVERSION_CODENAME=jammy\nUBUNTU_CODENAME=jammy\n";
+    const MINT: &str = "NAME=\"Linux Mint\"\nVERSION=\"21.3 (Virginia)\"\nID=linuxmint\nID_LIKE=\"ubuntu debian\"\nPRETTY_NAME=\"Linux Mint 21.3\"\nVERSION_ID=\"21.3\"\nVERSION_CODENAME=virginia\nUBUNTU_CODENAME=jammy\n";
+
+    #[test]
+    fn os_release_samples_map_to_their_distribution() {
+        let cases = [
+            (ARCH, Distribution::Arch),
+            (FEDORA, Distribution::Fedora),
+            (DEBIAN, Distribution::Debian),
+            (UBUNTU, Distribution::Ubuntu),
+            (GENTOO, Distribution::Gentoo),
+            (VOID, Distribution::Void),
+            (ENDEAVOUROS, Distribution::Arch),
+            (CACHYOS, Distribution::Arch),
+            (POP_OS, Distribution::Ubuntu),
+            (MINT, Distribution::Ubuntu),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(distribution_from_os_release(content), Some(expected), "{}", content);
+        }
+        assert_eq!(distribution_from_os_release("NAME=\"NixOS\"\nID=nixos\n"), None);
+    }
+
+    #[test]
+    fn os_release_value_handles_quotes_comments_and_crlf() {
+        assert_eq!(os_release_value(FEDORA, "VERSION").as_deref(), Some("40 (Workstation Edition)"));
+        assert_eq!(os_release_value(FEDORA, "VERSION_CODENAME").as_deref(), Some(""));
+        assert_eq!(os_release_value(ENDEAVOUROS, "ID").as_deref(), Some("endeavouros"));
+        assert_eq!(os_release_value(DEBIAN, "VERSION_CODENAME").as_deref(), Some("bookworm"));
+        assert_eq!(os_release_value(DEBIAN, "ID_LIKE"), None);
+
+        let content = "# comment\r\nID = \"ubuntu\" \r\nNAME=\"Say \\\"hi\\\"\"\r\nID=ubuntu # trailing\r\n";
+        assert_eq!(os_release_value(content, "ID").as_deref(), Some("ubuntu"));
+        assert_eq!(os_release_value(content, "NAME").as_deref(), Some("Say \"hi\""));
+    }
+
+    #[test]
+    fn ubuntu_derivatives_use_the_ubuntu_installer() {
+        for content in [POP_OS, MINT] {
+            let dist = distribution_from_os_release(content);
+            assert_eq!(dist, Some(Distribution::Ubuntu), "{}", content);
+            assert_eq!(dist.map(Distribution::manifest_key), Some("ubuntu"));
+        }
+    }
+}
@@ -1,24 +1,11 @@
-use std::{env, path::PathBuf, process::Command};
+use std::{fs, path::{Path, PathBuf}, process::Command};
 
 use crate::config;
+use crate::error::{CliError, CliResult};
+use crate::privilege::Escalation;
 use crate::ui;
 
-fn find_shell_installation_path() -> Option<PathBuf> {
-    // Check both possible installation locations
-    let old_path = PathBuf::from("/etc/xdg/quickshell/noctalia-shell");
-    let home = env::var("HOME").ok()?;
-    let new_path = PathBuf::from(&home).join(".config/quickshell/noctalia-shell");
-    
-    if old_path.exists() {
-        Some(old_path)
-    } else if new_path.exists() {
-        Some(new_path)
-    } else {
-        None
-    }
-}
-
-fn is_systemd_running() -> bool {
+pub fn is_systemd_running() -> bool {
     // Check if systemd is running by checking for /run/systemd/system
     // or by checking if systemctl exists and can be run
     if PathBuf::from("/run/systemd/system").exists() {
@@ -33,77 +20,281 @@ fn is_systemd_running() -> bool {
         .unwrap_or(false)
 }
 
-pub fn run() {
+pub const DEFAULT_CONFIG_NAME: &str = "noctalia-shell";
+
+/// Whether `name` is safe to put in the unit's ExecStart: letters, digits,
+/// `.`, `_` and `-` only
+fn is_valid_config_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Write `unit` into a fresh private (0700) directory under the scratch
+/// dir, so no other user can swap the file before root copies it. The file
+/// is removed when the returned guard is dropped.
+fn stage_unit(unit: &str) -> CliResult<(tempfile::TempDir, PathBuf)> {
+    let scratch = config::temp_dir();
+    let dir = fs::create_dir_all(&scratch)
+        .and_then(|_| tempfile::Builder::new().prefix("noctalia-unit-").tempdir_in(&scratch))
+        .map_err(|e| CliError::failure(format!("Failed to create a scratch directory in {}: {}", scratch.display(), e)))?;
+    let file = dir.path().join(UNIT_NAME);
+    fs::write(&file, unit).map_err(|e| CliError::failure(format!("Failed to write the new unit: {}", e)))?;
+    Ok((dir, file))
+}
+
+/// Directory the user unit is installed into when nothing else says otherwise
+pub const DEFAULT_UNIT_DIR: &str = "/usr/lib/systemd/user";
+const UNIT_NAME: &str = "noctalia.service";
+
+/// The distribution's user unit directory, as systemd's pkg-config file reports it
+fn detect_unit_dir() -> Option<PathBuf> {
+    let output = Command::new("pkg-config")
+        .args(["--variable=systemduserunitdir", "systemd"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!dir.is_empty()).then(|| PathBuf::from(dir))
+}
+
+/// Where the user unit goes: `[systemd] unit_dir`, then the directory
+/// detected with pkg-config, then `/usr/lib/systemd/user`
+pub fn unit_dir() -> (PathBuf, config::Origin) {
+    let configured = config::CliConfig::load().ok().and_then(|(cfg, _)| cfg.systemd.unit_dir);
+    match configured {
+        Some(dir) => (dir, config::Origin::Config),
+        None => match detect_unit_dir() {
+            Some(dir) => (dir, config::Origin::Default),
+            None => (PathBuf::from(DEFAULT_UNIT_DIR), config::Origin::Default),
+        },
+    }
+}
+
+/// Path of the installed user unit
+pub fn unit_path() -> PathBuf {
+    unit_dir().0.join(UNIT_NAME)
+}
+
+/// Whether `install systemd` has put the unit in place
+pub fn is_installed() -> bool {
+    unit_path().exists()
+}
+
+fn systemctl_user(args: &[&str]) -> Result<(), String> {
+    match Command::new("systemctl").arg("--user").args(args).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("systemctl exited with {}", status)),
+        Err(e) => Err(format!("failed to run systemctl: {}", e)),
+    }
+}
+
+/// Stop, disable and remove the user unit, then reload the daemon. Stopping
+/// and disabling are best effort; only failing to delete the file is an error.
+pub fn uninstall() -> CliResult {
+    ui::step(&format!("Removing {}", UNIT_NAME));
+    if let Err(e) = systemctl_user(&["stop", UNIT_NAME]) {
+        ui::warn(&format!("Could not stop {}: {}", UNIT_NAME, e));
+    }
+    if let Err(e) = systemctl_user(&["disable", UNIT_NAME]) {
+        ui::warn(&format!("Could not disable {}: {}", UNIT_NAME, e));
+    }
+
+    let escalation = Escalation::detect()?;
+    escalation.announce();
+    let unit = unit_path();
+    let unit_str = unit.to_str().expect("unit path is valid UTF-8");
+    escalation.run(&["rm", "-f", unit_str])
+        .map_err(|e| CliError::failure(format!("Failed to remove {}: {}", unit.display(), e)))?;
+
+    match systemctl_user(&["daemon-reload"]) {
+        Ok(()) => ui::success("Systemd daemon reloaded"),
+        Err(e) => ui::warn(&format!("Failed to reload systemd daemon: {}", e)),
+    }
+    ui::success(&format!("Removed {}", UNIT_NAME));
+    Ok(())
+}
+
+/// Rewrite `-c noctalia-shell` in the unit's ExecStart lines to use
+/// `config_name`. Returns `None` if no ExecStart line matches the expected
+/// pattern, in which case the unit is left untouched.
+fn template_unit(unit: &str, config_name: &str) -> Option<String> {
+    let needle = format!("-c {}", DEFAULT_CONFIG_NAME);
+    let replacement = format!("-c {}", config_name);
+    let mut matched = false;
+
+    let lines: Vec<String> = unit
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("ExecStart=") && line.contains(&needle) {
+                matched = true;
+                line.replace(&needle, &replacement)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+
+    matched.then(|| lines.join("\n") + "\n")
+}
+
+/// Service file shipped inside the shell installation
+const SHIPPED_UNIT: &str = "Assets/Services/systemd/noctalia.service";
+
+/// Quickshell config name in the `-c NAME` of an ExecStart line
+fn exec_config_name(unit: &str) -> Option<&str> {
+    unit.lines()
+        .filter(|line| line.trim_start().starts_with("ExecStart="))
+        .find_map(|line| {
+            let mut words = line.split_whitespace();
+            words.find(|w| *w == "-c")?;
+            words.next()
+        })
+}
+
+/// Bring the installed unit in line with the one shipped by the current
+/// shell, keeping its config name. Asks first unless `requested`; the unit's
+/// enabled and active state are left alone. Without `requested`, problems
+/// are warnings so they don't fail the update that triggered the check.
+pub fn refresh(requested: bool) -> CliResult {
+    if !is_installed() {
+        if requested {
+            ui::info("The systemd service is not installed; nothing to refresh");
+        }
+        return Ok(());
+    }
+    let Some(shipped) = config::find_shell_path().and_then(|p| fs::read_to_string(p.join(SHIPPED_UNIT)).ok()) else {
+        return Ok(());
+    };
+    let unit = unit_path();
+    let installed = fs::read_to_string(&unit).unwrap_or_default();
+    let wanted = match exec_config_name(&installed) {
+        Some(name) if name != DEFAULT_CONFIG_NAME => template_unit(&shipped, name).unwrap_or(shipped),
+        _ => shipped,
+    };
+    if wanted == installed {
+        if requested {
+            ui::success(&format!("{} is up to date", UNIT_NAME));
+        }
+        return Ok(());
+    }
+
+    if !requested {
+        ui::info(&format!("The updated shell ships a different {}", UNIT_NAME));
+        if !ui::assume_yes() && !ui::is_interactive() {
+            ui::info("Refresh it with: noctalia update shell --refresh-service");
+            return Ok(());
+        }
+        if !ui::confirm(&format!("Refresh the installed {}?", UNIT_NAME), true)? {
+            return Ok(());
+        }
+    }
+
+    let result = (|| {
+        let escalation = Escalation::detect()?;
+        let (_staged, temp) = stage_unit(&wanted)?;
+        ui::step(&format!("Refreshing {}", unit.display()));
+        escalation
+            .run(&["install", "-m", "644", &temp.to_string_lossy(), &unit.to_string_lossy()])
+            .map_err(|e| CliError::failure(format!("Failed to refresh {}: {}", unit.display(), e)))
+    })();
+    if let Err(e) = result {
+        if requested {
+            return Err(e);
+        }
+        ui::warn(&e.message);
+        return Ok(());
+    }
+
+    match systemctl_user(&["daemon-reload"]) {
+        Ok(()) => ui::success(&format!("{} refreshed", UNIT_NAME)),
+        Err(e) => ui::warn(&format!("Failed to reload systemd daemon: {}", e)),
+    }
+    if systemctl_user(&["--quiet", "is-active", UNIT_NAME]).is_ok() {
+        ui::info(&format!("Restart it to use the new unit: systemctl --user restart {}", UNIT_NAME));
+    }
+    Ok(())
+}
+
+/// Install the user unit, into `dir` if given (remembered for later
+/// status and uninstall), otherwise into the configured or detected directory
+pub fn run(config_name: &str, dir: Option<&Path>) -> CliResult {
     ui::section("Install Systemd Service");
-    
+    if !is_valid_config_name(config_name) {
+        return Err(CliError::usage(format!("Invalid config name '{}'", config_name))
+            .with_hint("Use only letters, digits, '.', '_' and '-'."));
+    }
+
     // Check if shell is installed
     let (cfg, _path) = config::CliConfig::load().expect("load config");
     if !cfg.is_component_installed("shell") {
-        ui::error("Noctalia shell is not installed. Run 'noctalia install shell' first.");
-        std::process::exit(1);
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
     }
     
     // Check if systemd is running
     ui::step("Checking if systemd is available");
     if !is_systemd_running() {
-        ui::error("Systemd is not running on this system.");
-        ui::info("This command is only available on systems using systemd.");
-        std::process::exit(1);
+        return Err(CliError::failure("Systemd is not running on this system.")
+            .with_hint("This command is only available on systems using systemd."));
     }
     
     ui::info("Systemd is available");
     
     // Find the shell installation path
-    let shell_path = match find_shell_installation_path() {
+    let shell_path = match config::find_shell_path() {
         Some(path) => path,
         None => {
-            ui::error("Could not find noctalia-shell installation directory.");
-            std::process::exit(1);
+            return Err(CliError::failure("Could not find noctalia-shell installation directory."));
         }
     };
     
     // Locate the service file
-    let service_file = shell_path.join("Assets/Services/systemd/noctalia.service");
+    let service_file = shell_path.join(SHIPPED_UNIT);
     if !service_file.exists() {
-        ui::error(&format!("Service file not found at: {}", service_file.display()));
-        ui::info("The service file should be located at: Assets/Services/systemd/noctalia.service");
-        std::process::exit(1);
+        return Err(CliError::failure(format!("Service file not found at: {}", service_file.display()))
+            .with_hint("The service file should be located at: Assets/Services/systemd/noctalia.service"));
     }
     
-    ui::step("Installing systemd user service");
-    ui::info("This operation requires sudo permissions. You will be prompted for your password.");
-    
-    // Create target directory and copy service file using sudo
-    let target_dir = "/usr/lib/systemd/user";
-    let target_file = format!("{}/noctalia.service", target_dir);
-    
-    // Use sudo to create directory, copy file, and set permissions
-    let service_file_str = service_file.to_str().unwrap();
-    let cmd = format!(
-        "mkdir -p '{}' && cp '{}' '{}' && chmod 644 '{}'",
-        target_dir, service_file_str, target_file, target_file
-    );
-    
-    let status = Command::new("sudo")
-        .args(["sh", "-c", &cmd])
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status();
-    
-    match status {
-        Ok(exit_status) => {
-            if !exit_status.success() {
-                ui::error("Failed to install service file");
-                std::process::exit(1);
+    // Point the unit at a renamed quickshell config if requested
+    let mut unit_source = service_file.clone();
+    let mut staged = None;
+    if config_name != DEFAULT_CONFIG_NAME {
+        let unit = fs::read_to_string(&service_file)
+            .map_err(|e| CliError::failure(format!("Failed to read service file: {}", e)))?;
+        match template_unit(&unit, config_name) {
+            Some(templated) => {
+                let (dir, file) = stage_unit(&templated)?;
+                ui::info(&format!("Using quickshell config '{}' in ExecStart", config_name));
+                unit_source = file;
+                staged = Some(dir);
+            }
+            None => {
+                ui::warn("Service file does not use the expected 'qs -c noctalia-shell' ExecStart; installing it unchanged");
             }
-        }
-        Err(e) => {
-            ui::error(&format!("Failed to install service file: {}", e));
-            std::process::exit(1);
         }
     }
-    
+
+    ui::step("Installing systemd user service");
+    let escalation = Escalation::detect()?;
+    escalation.announce();
+
+    let target_dir = match dir {
+        Some(dir) => {
+            let (mut cfg, path) = config::CliConfig::load().expect("load config");
+            cfg.systemd.unit_dir = Some(dir.to_path_buf());
+            cfg.save_or_warn(&path);
+            dir.to_path_buf()
+        }
+        None => unit_dir().0,
+    };
+    ui::info(&format!("Installing the unit into {}", target_dir.display()));
+
+    // Create the target directory and copy the unit as root
+    let target_file = target_dir.join(UNIT_NAME);
+    let copied = escalation
+        .run(&["mkdir", "-p", &target_dir.to_string_lossy()])
+        .and_then(|_| escalation.run(&["install", "-m", "644", &unit_source.to_string_lossy(), &target_file.to_string_lossy()]));
+    drop(staged);
+    copied.map_err(|e| CliError::failure(format!("Failed to install service file: {}", e)))?;
+
     ui::success("Service file installed successfully");
     
     // Reload systemd daemon
@@ -117,21 +308,16 @@ pub fn run() {
             if exit_status.success() {
                 ui::success("Systemd daemon reloaded");
             } else {
-                ui::error("Failed to reload systemd daemon");
+                ui::warn("Failed to reload systemd daemon");
             }
         }
         Err(e) => {
-            ui::error(&format!("Failed to reload systemd daemon: {}", e));
+            ui::warn(&format!("Failed to reload systemd daemon: {}", e));
         }
     }
     
     // Ask if user wants to enable the service
-    use dialoguer::{theme::ColorfulTheme, Confirm};
-    let theme = ColorfulTheme::default();
-    let should_enable = Confirm::with_theme(&theme)
-        .with_prompt("Would you like to enable the noctalia.service?")
-        .interact()
-        .unwrap_or(false);
+    let should_enable = ui::confirm("Would you like to enable the noctalia.service?", false)?;
     
     if should_enable {
         ui::step("Enabling noctalia.service");
@@ -145,10 +331,7 @@ pub fn run() {
                     ui::success("Service enabled successfully");
                     
                     // Ask if user wants to start it now
-                    let should_start = Confirm::with_theme(&theme)
-                        .with_prompt("Would you like to start the service now?")
-                        .interact()
-                        .unwrap_or(false);
+                    let should_start = ui::confirm("Would you like to start the service now?", false)?;
                     
                     if should_start {
                         ui::step("Starting noctalia.service");
@@ -185,5 +368,6 @@ pub fn run() {
         ui::info("  systemctl --user enable noctalia.service");
         ui::info("  systemctl --user start noctalia.service");
     }
-}
 
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::error::{CliError, CliResult, ExitCode};
+use crate::ui;
+
+/// Set by the first Ctrl-C while an operation defers interruption
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Number of live `Deferred` guards
+static DEFERRING: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle Ctrl-C. Normally it exits at once with 130; while a `defer` guard
+/// is alive it only records the request, so the operation can stop at a safe
+/// point and clean up. A second Ctrl-C always exits.
+pub fn install_handler() {
+    let result = ctrlc::set_handler(|| {
+        if DEFERRING.load(Ordering::SeqCst) == 0 || REQUESTED.swap(true, Ordering::SeqCst) {
+            eprintln!();
+            std::process::exit(ExitCode::Interrupted.code());
+        }
+        ui::warn("Interrupted; stopping at the next safe point (press Ctrl-C again to force)");
+    });
+    if let Err(e) = result {
+        ui::warn(&format!("Could not handle Ctrl-C: {}", e));
+    }
+}
+
+/// Keeps Ctrl-C from exiting immediately until dropped
+pub struct Deferred(());
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        DEFERRING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Defer Ctrl-C for the lifetime of the returned guard; poll `requested` or
+/// `check` between steps
+pub fn defer() -> Deferred {
+    DEFERRING.fetch_add(1, Ordering::SeqCst);
+    Deferred(())
+}
+
+/// Whether Ctrl-C was pressed while deferred
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Fail with exit code 130 if Ctrl-C was pressed; call before each step that
+/// must not be started once the user asked to stop
+pub fn check() -> CliResult {
+    if requested() {
+        return Err(CliError::new(ExitCode::Interrupted, "Interrupted"));
+    }
+    Ok(())
+}
@@ -1,65 +1,217 @@
-use std::process::Command;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::config;
+use crate::error::{CliError, CliResult, ExitCode};
+use crate::interrupt;
+use crate::output::{self, IpcTarget, IpcTargets};
+use crate::run::shell::{check_config_resolvable, is_noctalia_running};
 use crate::ui;
 
-fn is_noctalia_running() -> bool {
-    // Check if quickshell is running with noctalia-shell
-    // We check for processes that match "qs" and contain "noctalia-shell"
-    let output = Command::new("pgrep")
-        .args(["-f", "qs.*noctalia-shell"])
-        .output();
-    
-    match output {
-        Ok(output) => output.status.success(),
-        Err(_) => {
-            // If pgrep fails, try using ps as fallback
-            let ps_output = Command::new("ps")
-                .args(["-eo", "cmd"])
-                .output();
-            
-            match ps_output {
-                Ok(ps_output) => {
-                    let stdout = String::from_utf8_lossy(&ps_output.stdout);
-                    stdout.lines().any(|line| {
-                        line.contains("qs") && line.contains("noctalia-shell")
-                    })
-                }
-                Err(_) => false,
+fn check_prerequisites() -> CliResult {
+    // Check if shell is installed
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    if !cfg.is_component_installed("shell") {
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
+    }
+    check_config_resolvable()?;
+
+    // Check if noctalia-shell is running (only show message if not running)
+    if !is_noctalia_running() {
+        return Err(CliError::failure("Noctalia shell is not running. Run 'noctalia run' first."));
+    }
+    Ok(())
+}
+
+fn query_ipc_show() -> std::io::Result<std::process::Output> {
+    // Execute qs -c noctalia-shell ipc show
+    Command::new("qs")
+        .arg("-c")
+        .arg("noctalia-shell")
+        .arg("ipc")
+        .arg("show")
+        .output()
+}
+
+/// How long `ipc ping` waits for the shell to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why the shell did not answer a ping
+enum PingFailure {
+    NotRunning,
+    Spawn(io::Error),
+    Failed(std::process::ExitStatus),
+    Timeout,
+}
+
+/// Time an `ipc show` round-trip, giving up after `PING_TIMEOUT`
+fn ping() -> Result<Duration, PingFailure> {
+    if !is_noctalia_running() {
+        return Err(PingFailure::NotRunning);
+    }
+
+    let started = Instant::now();
+    let mut child = Command::new("qs")
+        .args(["-c", "noctalia-shell", "ipc", "show"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(PingFailure::Spawn)?;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(started.elapsed()),
+            Ok(Some(status)) => return Err(PingFailure::Failed(status)),
+            Ok(None) if started.elapsed() < PING_TIMEOUT => thread::sleep(Duration::from_millis(20)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(PingFailure::Timeout);
             }
         }
     }
 }
 
-fn check_prerequisites() {
-    // Check if shell is installed
+/// Liveness check: exit 0 if the shell answers an `ipc show` round-trip in
+/// time, non-zero otherwise. Silent unless --verbose.
+pub fn run_ping() -> CliResult {
+    let verbose = ui::is_verbose();
+    let (message, code) = match ping() {
+        Ok(elapsed) => {
+            if verbose {
+                ui::success(&format!("noctalia-shell is responding ({} ms)", elapsed.as_millis()));
+            }
+            return Ok(());
+        }
+        Err(PingFailure::NotRunning) => ("noctalia-shell is not running".to_string(), ExitCode::Failure),
+        Err(PingFailure::Spawn(e)) => (format!("Failed to run qs: {}", e), ExitCode::Failure),
+        Err(PingFailure::Failed(status)) => {
+            (format!("noctalia-shell IPC failed ({})", status), ExitCode::Child(status.code().unwrap_or(1)))
+        }
+        Err(PingFailure::Timeout) => {
+            (format!("noctalia-shell did not answer within {}s", PING_TIMEOUT.as_secs()), ExitCode::Failure)
+        }
+    };
+    if verbose {
+        ui::error(&message);
+    }
+    Err(CliError::silent(code))
+}
+
+/// How often `ipc watch` checks whether the shell is up
+const WATCH_POLL: Duration = Duration::from_secs(2);
+
+/// Send a call whenever the shell comes up: once now if it is running, then
+/// again after every restart, until interrupted with Ctrl-C
+pub fn run_watch(target: String, function: String, args: Vec<String>, validate: bool) -> CliResult {
+    ui::section("Noctalia IPC Watch");
     let (cfg, _path) = config::CliConfig::load().expect("load config");
     if !cfg.is_component_installed("shell") {
-        ui::error("Noctalia shell is not installed. Run 'noctalia install shell' first.");
-        std::process::exit(1);
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
     }
+    check_config_resolvable()?;
 
-    // Check if noctalia-shell is running (only show message if not running)
-    if !is_noctalia_running() {
-        ui::error("Noctalia shell is not running. Run 'noctalia run' first.");
-        std::process::exit(1);
+    let _defer = interrupt::defer();
+
+    ui::info(&format!("Watching noctalia-shell; sending {} {} on every start (Ctrl-C to stop)", target, function));
+    let mut up = false;
+    let mut validated = !validate;
+    while !interrupt::requested() {
+        let now_up = ping().is_ok();
+        if now_up && !up {
+            if !validated {
+                validate_call(&target, &function)?;
+                validated = true;
+            }
+            ui::step(&format!("noctalia-shell is up, sending {} {}", target, function));
+            match send_call(&target, &function, &args) {
+                Ok(()) => ui::success("Sent"),
+                // The shell may have gone down again; the next start retries
+                Err(e) if matches!(e.code, ExitCode::Child(_)) => ui::warn(&e.message),
+                Err(e) => return Err(e),
+            }
+        } else if up && !now_up {
+            ui::warn("noctalia-shell went down; waiting for it to come back");
+        }
+        up = now_up;
+
+        // Sleep in short steps so Ctrl-C is handled promptly
+        let started = Instant::now();
+        while started.elapsed() < WATCH_POLL && !interrupt::requested() {
+            thread::sleep(Duration::from_millis(100));
+        }
     }
+
+    ui::info("Stopped watching");
+    Ok(())
 }
 
-pub fn run_call(target: String, function: String) {
-    ui::section("Noctalia IPC Call");
-    check_prerequisites();
-    
-    ui::step(&format!("Sending IPC call: {} {}", target, function));
-    
-    // Execute qs -c noctalia-shell ipc call <target> <function>
+fn function_name(signature: &str) -> &str {
+    signature.split('(').next().unwrap_or(signature)
+}
+
+fn closest_match<'a>(wanted: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    // Only suggest names that are reasonably close to what was typed
+    candidates
+        .map(|c| (strsim::levenshtein(wanted, c), c))
+        .filter(|(distance, c)| *distance <= 3.max(c.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// Targets listed by `qs ipc show`, or `None` if it could not be queried
+fn available_targets() -> Option<Vec<IpcTarget>> {
+    match query_ipc_show() {
+        Ok(output) if output.status.success() => Some(parse_ipc_show_output(&String::from_utf8_lossy(&output.stdout))),
+        _ => {
+            ui::warn("Could not query available IPC targets, skipping validation");
+            None
+        }
+    }
+}
+
+fn check_call(targets: &[IpcTarget], target: &str, function: &str) -> CliResult {
+    let Some(found) = targets.iter().find(|t| t.name == target) else {
+        let mut err = CliError::failure(format!("Unknown IPC target '{}'", target));
+        if let Some(suggestion) = closest_match(target, targets.iter().map(|t| t.name.as_str())) {
+            err = err.with_hint(format!("Did you mean '{}'?", suggestion));
+        }
+        return Err(err.with_hint("Run 'noctalia ipc show' to list available targets and functions."));
+    };
+
+    if !found.functions.iter().any(|f| function_name(f) == function) {
+        let mut err = CliError::failure(format!("Unknown function '{}' for target '{}'", function, target));
+        if let Some(suggestion) = closest_match(function, found.functions.iter().map(|f| function_name(f))) {
+            err = err.with_hint(format!("Did you mean '{}'?", suggestion));
+        }
+        return Err(err.with_hint(format!("Run 'noctalia ipc show' to list the functions of '{}'.", target)));
+    }
+    Ok(())
+}
+
+fn validate_call(target: &str, function: &str) -> CliResult {
+    ui::step("Validating IPC target and function");
+    match available_targets() {
+        Some(targets) => check_call(&targets, target, function),
+        None => Ok(()),
+    }
+}
+
+fn send_call(target: &str, function: &str, args: &[String]) -> CliResult {
+    // Execute qs -c noctalia-shell ipc call <target> <function> [args...]
     let status = Command::new("qs")
         .arg("-c")
         .arg("noctalia-shell")
         .arg("ipc")
         .arg("call")
-        .arg(&target)
-        .arg(&function)
+        .arg(target)
+        .arg(function)
+        .args(args)
         .stdin(std::process::Stdio::inherit())
         .stdout(std::process::Stdio::inherit())
         .stderr(std::process::Stdio::inherit())
@@ -68,15 +220,176 @@ pub fn run_call(target: String, function: String) {
     match status {
         Ok(exit_status) => {
             if !exit_status.success() {
-                std::process::exit(exit_status.code().unwrap_or(1));
+                let code = exit_status.code().unwrap_or(1);
+                return Err(CliError::new(ExitCode::Child(code), format!("IPC call failed with status {}", code)));
+            }
+            Ok(())
+        }
+        Err(e) => Err(CliError::failure(format!("Failed to send IPC call: {}", e))
+            .with_hint("Make sure 'qs' (quickshell) is installed and available in your PATH.")),
+    }
+}
+
+/// Read the whole of stdin as one argument, dropping a single trailing newline
+fn read_stdin_payload() -> CliResult<String> {
+    let mut payload = String::new();
+    io::stdin()
+        .read_to_string(&mut payload)
+        .map_err(|e| CliError::failure(format!("Failed to read IPC payload from stdin: {}", e)))?;
+    if payload.ends_with('\n') {
+        payload.pop();
+        if payload.ends_with('\r') {
+            payload.pop();
+        }
+    }
+    Ok(payload)
+}
+
+pub fn run_call(
+    target: String,
+    function: String,
+    mut args: Vec<String>,
+    stdin: bool,
+    validate: bool,
+    repeat: u32,
+    interval_ms: u64,
+) -> CliResult {
+    ui::section("Noctalia IPC Call");
+    check_prerequisites()?;
+
+    // The payload goes after any arguments given on the command line
+    if stdin {
+        args.push(read_stdin_payload()?);
+    }
+
+    if validate {
+        validate_call(&target, &function)?;
+    }
+    
+    ui::step(&format!("Sending IPC call: {} {}", target, function));
+
+    if repeat <= 1 {
+        return send_call(&target, &function, &args);
+    }
+
+    let mut failures = 0;
+    for i in 1..=repeat {
+        let result = send_call(&target, &function, &args);
+        if ui::is_verbose() {
+            match &result {
+                Ok(()) => ui::info(&format!("[{}/{}] ok", i, repeat)),
+                Err(e) => ui::error(&format!("[{}/{}] {}", i, repeat, e)),
+            }
+        }
+        if let Err(e) = result {
+            // Spawning qs itself failed; repeating won't help
+            if !matches!(e.code, ExitCode::Child(_)) {
+                return Err(e);
+            }
+            failures += 1;
+        }
+        if i < repeat && interval_ms > 0 {
+            thread::sleep(Duration::from_millis(interval_ms));
+        }
+    }
+
+    if failures > 0 {
+        return Err(CliError::failure(format!("{} of {} IPC calls failed", failures, repeat)));
+    }
+    ui::success(&format!("{}/{} IPC calls succeeded", repeat, repeat));
+    Ok(())
+}
+
+/// One `target function args...` line of a batch file
+struct BatchCall {
+    line: usize,
+    target: String,
+    function: String,
+    args: Vec<String>,
+}
+
+/// Parse a batch file: one call per line with shell-style quoting, blank
+/// lines and `#` comments ignored
+fn parse_batch(content: &str) -> CliResult<Vec<BatchCall>> {
+    let mut calls = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let words = shell_words::split(trimmed)
+            .map_err(|e| CliError::usage(format!("Line {}: {}", line_no, e)))?;
+        let mut words = words.into_iter();
+        match (words.next(), words.next()) {
+            (Some(target), Some(function)) => {
+                calls.push(BatchCall { line: line_no, target, function, args: words.collect() })
+            }
+            _ => {
+                return Err(CliError::usage(format!("Line {}: expected 'target function [args...]'", line_no)));
             }
         }
-        Err(e) => {
-            ui::error(&format!("Failed to send IPC call: {}", e));
-            ui::info("Make sure 'qs' (quickshell) is installed and available in your PATH.");
-            std::process::exit(1);
+    }
+    Ok(calls)
+}
+
+/// Run every call in `path` (or stdin for `-`) in order, stopping at the
+/// first failure unless `keep_going` is set
+pub fn run_batch(path: &str, validate: bool, keep_going: bool) -> CliResult {
+    ui::section("Noctalia IPC Batch");
+
+    let content = if path == "-" {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| CliError::failure(format!("Failed to read batch from stdin: {}", e)))?;
+        content
+    } else {
+        fs::read_to_string(path).map_err(|e| CliError::failure(format!("Failed to read batch file {}: {}", path, e)))?
+    };
+    // Reject a malformed file before sending anything
+    let calls = parse_batch(&content)?;
+    if calls.is_empty() {
+        ui::info("Batch file contains no calls");
+        return Ok(());
+    }
+
+    check_prerequisites()?;
+    let targets = if validate {
+        ui::step("Validating IPC targets and functions");
+        available_targets()
+    } else {
+        None
+    };
+    if let Some(targets) = &targets {
+        for call in &calls {
+            check_call(targets, &call.target, &call.function)
+                .map_err(|e| CliError { message: format!("Line {}: {}", call.line, e.message), ..e })?;
+        }
+    }
+
+    let mut failures = 0;
+    for (i, call) in calls.iter().enumerate() {
+        ui::step(&format!("Sending IPC call: {} {}", call.target, call.function));
+        if let Err(e) = send_call(&call.target, &call.function, &call.args) {
+            ui::error(&format!("Line {}: {}", call.line, e));
+            failures += 1;
+            // Spawning qs itself failed; the remaining calls would fail too
+            if !keep_going || !matches!(e.code, ExitCode::Child(_)) {
+                return Err(CliError::new(
+                    e.code,
+                    format!("Batch stopped at line {} after {} successful call(s)", call.line, i + 1 - failures),
+                )
+                .with_hint("Use --keep-going to run the remaining calls after a failure."));
+            }
         }
     }
+
+    if failures > 0 {
+        return Err(CliError::failure(format!("{} of {} IPC calls failed", failures, calls.len())));
+    }
+    ui::success(&format!("{}/{} IPC calls succeeded", calls.len(), calls.len()));
+    Ok(())
 }
 
 fn format_function_signature(func_sig: &str) -> String {
@@ -118,81 +431,134 @@ fn format_function_signature(func_sig: &str) -> String {
     }
 }
 
-fn format_ipc_show_output(output: &str) {
-    let mut current_target: Option<String> = None;
-    let mut functions: Vec<String> = Vec::new();
-    
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ShowFormat {
+    /// Targets with their functions as a bulleted list
+    #[default]
+    Tree,
+    /// One tab-separated `target<TAB>function` pair per line
+    Plain,
+    /// Structured JSON object with the list of targets
+    Json,
+}
+
+pub fn parse_ipc_show_output(output: &str) -> Vec<IpcTarget> {
+    let mut targets: Vec<IpcTarget> = Vec::new();
+
     for line in output.lines() {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
-        
+
         if line.starts_with("target ") {
-            // If we have a previous target, format it
-            if let Some(target) = current_target.take() {
-                ui::info(&format!("{}", target));
-                for func in &functions {
-                    println!("  • {}", func);
-                }
-                println!();
-                functions.clear();
-            }
-            // Set new target
-            current_target = Some(line.trim_start_matches("target ").to_string());
+            targets.push(IpcTarget {
+                name: line.trim_start_matches("target ").to_string(),
+                functions: Vec::new(),
+            });
         } else if line.starts_with("function ") {
             // Extract function signature and format it
             let func_sig = line.trim_start_matches("function ");
-            let formatted = format_function_signature(func_sig);
-            functions.push(formatted);
+            if let Some(target) = targets.last_mut() {
+                target.functions.push(format_function_signature(func_sig));
+            }
         }
     }
-    
-    // Handle the last target
-    if let Some(target) = current_target {
-        ui::info(&format!("{}", target));
-        for func in &functions {
-            println!("  • {}", func);
+
+    targets
+}
+
+fn print_tree(targets: &[IpcTarget]) {
+    for (i, target) in targets.iter().enumerate() {
+        if i > 0 {
+            ui::blank();
+        }
+        ui::info(&target.name);
+        for func in &target.functions {
+            ui::item(func);
+        }
+    }
+}
+
+fn print_plain(targets: &[IpcTarget]) {
+    for target in targets {
+        for func in &target.functions {
+            println!("{}\t{}", target.name, func);
         }
     }
 }
 
-pub fn run_show() {
-    ui::section("Noctalia IPC Show");
-    check_prerequisites();
+fn print_json(targets: &[IpcTarget]) -> CliResult {
+    output::print(&IpcTargets { targets })
+}
+
+/// Keep only the target named `name`, failing with the available names
+fn filter_target(targets: Vec<IpcTarget>, name: &str) -> CliResult<Vec<IpcTarget>> {
+    let names: Vec<String> = targets.iter().map(|t| t.name.clone()).collect();
+    let matching: Vec<IpcTarget> = targets.into_iter().filter(|t| t.name == name).collect();
+    if !matching.is_empty() {
+        return Ok(matching);
+    }
+
+    let mut err = CliError::new(ExitCode::NotFound, format!("No such IPC target '{}'", name));
+    if let Some(suggestion) = closest_match(name, names.iter().map(String::as_str)) {
+        err = err.with_hint(format!("Did you mean '{}'?", suggestion));
+    }
+    if !names.is_empty() {
+        err = err.with_hint(format!("Available targets: {}", names.join(", ")));
+    }
+    Err(err)
+}
+
+/// List IPC targets in `format`, or with `raw` print quickshell's output
+/// exactly as it came
+pub fn run_show(format: ShowFormat, only: Option<&str>, raw: bool) -> CliResult {
+    let decorated = format == ShowFormat::Tree && !raw;
+    if decorated {
+        ui::section("Noctalia IPC Show");
+    }
+    check_prerequisites()?;
     
-    ui::step("Fetching available IPC targets and functions");
+    if decorated {
+        ui::step("Fetching available IPC targets and functions");
+    }
     
-    // Execute qs -c noctalia-shell ipc show
-    let output = Command::new("qs")
-        .arg("-c")
-        .arg("noctalia-shell")
-        .arg("ipc")
-        .arg("show")
-        .output();
-
-    match output {
+    match query_ipc_show() {
         Ok(output) => {
             if !output.status.success() {
-                ui::error("Failed to get IPC information");
-                std::process::exit(output.status.code().unwrap_or(1));
+                let code = output.status.code().unwrap_or(1);
+                return Err(CliError::new(ExitCode::Child(code), "Failed to get IPC information"));
             }
             
+            if raw {
+                io::stdout()
+                    .write_all(&output.stdout)
+                    .map_err(|e| CliError::failure(format!("Failed to write output: {}", e)))?;
+                return Ok(());
+            }
+
             let stdout = String::from_utf8_lossy(&output.stdout);
-            
-            if stdout.trim().is_empty() {
-                ui::info("No IPC targets found");
-            } else {
-                ui::info("Available IPC Targets and Functions:");
-                println!();
-                format_ipc_show_output(&stdout);
+            let mut targets = parse_ipc_show_output(&stdout);
+            if let Some(name) = only {
+                targets = filter_target(targets, name)?;
             }
+
+            match format {
+                ShowFormat::Tree => {
+                    if targets.is_empty() {
+                        ui::info("No IPC targets found");
+                    } else {
+                        ui::info("Available IPC Targets and Functions:");
+                        ui::blank();
+                        print_tree(&targets);
+                    }
+                }
+                ShowFormat::Plain => print_plain(&targets),
+                ShowFormat::Json => print_json(&targets)?,
+            }
+            Ok(())
         }
-        Err(e) => {
-            ui::error(&format!("Failed to get IPC information: {}", e));
-            ui::info("Make sure 'qs' (quickshell) is installed and available in your PATH.");
-            std::process::exit(1);
-        }
+        Err(e) => Err(CliError::failure(format!("Failed to get IPC information: {}", e))
+            .with_hint("Make sure 'qs' (quickshell) is installed and available in your PATH.")),
     }
 }
-
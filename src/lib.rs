@@ -0,0 +1,27 @@
+pub mod archive;
+pub mod components;
+pub mod config;
+pub mod doctor;
+pub mod download;
+pub mod error;
+pub mod http;
+pub mod install;
+pub mod interrupt;
+pub mod ipc;
+pub mod lock;
+pub mod notify;
+pub mod open;
+pub mod output;
+pub mod privilege;
+pub mod progress;
+pub mod purge;
+pub mod releases;
+pub mod run;
+pub mod status;
+pub mod ui;
+pub mod uninstall;
+pub mod update;
+pub mod version;
+
+pub use config::{CliConfig, ComponentConfig, SourceKind};
+pub use http::{CommitInfo, FetchError, ReleaseInfo, get_latest_commit_sha, get_latest_release_info, get_tag_commit_sha};
@@ -0,0 +1,52 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+};
+
+use fs2::FileExt;
+
+use crate::config;
+use crate::error::{CliError, CliResult};
+
+/// Exclusive lock on `<cache_dir>/noctalia.lock`, released when dropped
+pub struct Lock {
+    file: File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Take the lock that serializes operations touching the installation and
+/// config, failing immediately if another process holds it.
+pub fn acquire() -> CliResult<Lock> {
+    let dir = config::cache_dir();
+    fs::create_dir_all(&dir)
+        .map_err(|e| CliError::failure(format!("Failed to create cache dir {}: {}", dir.display(), e)))?;
+    let path = dir.join("noctalia.lock");
+    // Not truncated on open: the holder's PID must survive for the message below
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| CliError::failure(format!("Failed to open lockfile {}: {}", path.display(), e)))?;
+
+    if file.try_lock_exclusive().is_err() {
+        let mut holder = String::new();
+        let _ = file.read_to_string(&mut holder);
+        let err = CliError::failure("Another noctalia install or update is in progress.");
+        // The lock goes away with the process holding it, so point at that
+        // process rather than the file
+        return Err(match holder.trim().parse::<u32>() {
+            Ok(pid) => err.with_hint(format!("It is held by process {}; wait for it to finish or stop it.", pid)),
+            Err(_) => err.with_hint(format!("Find the process holding it with: fuser -v {}", path.display())),
+        });
+    }
+    // Record who holds the lock; failing to is harmless
+    let _ = file.set_len(0).and_then(|_| write!(file, "{}", std::process::id()));
+    Ok(Lock { file })
+}
@@ -1,13 +1,10 @@
-use clap::{Parser, Subcommand};
+use std::{path::PathBuf, time::Instant};
 
-mod install;
-mod update;
-mod run;
-mod ipc;
-mod config;
-mod ui;
+use clap::{Parser, Subcommand};
 
-pub use config::SourceKind;
+use noctalia::SourceKind;
+use noctalia::error::{CliError, CliResult, ExitCode};
+use noctalia::{components, config, doctor, download, http, install, interrupt, ipc, lock, notify, open, privilege, progress, purge, releases, run, status, ui, uninstall, update, version};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -16,11 +13,46 @@ pub use config::SourceKind;
     about = "Noctalia CLI",
     long_about = "A simple CLI for installing and updating Noctalia components.",
     arg_required_else_help = true,
-    help_template = "{about-with-newline}Usage:\n  {usage}\n\nCommands:\n{subcommands}\nOptions:\n{options}\n\nExamples:\n  noctalia install shell --release\n  noctalia install systemd\n  noctalia update shell\n  noctalia run\n  noctalia ipc <target> <function>\n  noctalia ipc show\n"
+    help_template = "{about-with-newline}Usage:\n  {usage}\n\nCommands:\n{subcommands}\nOptions:\n{options}\n\nExamples:\n  noctalia install shell --release\n  noctalia install systemd\n  noctalia update shell\n  noctalia releases\n  noctalia run\n  noctalia ipc <target> <function>\n  noctalia ipc show\n  noctalia components\n  noctalia doctor\n"
 )]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Output format; with 'json' errors are reported as JSON on stderr
+    #[arg(long, global = true, value_enum, default_value_t = ui::OutputFormat::Text)]
+    output: ui::OutputFormat,
+    /// Print more detailed progress information
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Answer yes to all confirmation prompts
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// Plain text output: no colors, glyphs or box drawing
+    #[arg(long, global = true)]
+    plain: bool,
+    /// Hide package manager output, showing only a summary
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Cap download speed in bytes per second (suffixes k, M, G)
+    #[arg(long, global = true, value_name = "RATE", value_parser = http::parse_rate)]
+    limit_rate: Option<u64>,
+    /// Connect to GitHub over IPv4 only, for networks with broken IPv6 (also NOCTALIA_PREFER_IPV4=1)
+    #[arg(long, global = true)]
+    prefer_ipv4: bool,
+    /// DANGEROUS: skip TLS certificate verification. Only a last resort behind an
+    /// intercepting proxy; adding its CA to the system trust store is preferred
+    #[arg(long, visible_alias = "no-verify-ssl", global = true)]
+    insecure: bool,
+    /// Also trust the CA certificates in this PEM file, e.g. a corporate proxy's
+    /// (also NOCTALIA_CA_BUNDLE or [http] ca_bundle)
+    #[arg(long, global = true, value_name = "FILE")]
+    cacert: Option<PathBuf>,
+    /// Elevate with CMD instead of the first of sudo, doas or pkexec found, e.g. "doas"
+    #[arg(long, global = true, value_name = "CMD")]
+    sudo_command: Option<String>,
+    /// Keep cached state in DIR instead of the default (also NOCTALIA_CACHE_DIR)
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -37,28 +69,164 @@ enum Commands {
         help_template = "Update\n\nUsage:\n  {usage}\n\nComponents:\n{subcommands}\nOptions:\n{options}\n\nExamples:\n  noctalia update shell\n"
     )]
     Update(UpdateTargets),
+    #[command(
+        arg_required_else_help = true,
+        about = "Download a noctalia-shell tarball without installing it",
+        help_template = "Download\n\nUsage:\n  {usage}\n\nComponents:\n{subcommands}\nOptions:\n{options}\n\nExamples:\n  noctalia download shell --output-dir .\n"
+    )]
+    Download(DownloadTargets),
     #[command(
         about = "Run noctalia-shell",
         long_about = "Start the noctalia-shell using quickshell (qs -c noctalia-shell).",
-        help_template = "Run Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia run\n  noctalia run --debug\n"
+        help_template = "Run Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia run\n  noctalia run --debug\n  noctalia run --replace\n  noctalia run --wait\n  noctalia run --env QT_SCALE_FACTOR=1.5\n"
     )]
     Run {
         /// Run noctalia-shell with debug mode enabled (NOCTALIA_DEBUG=1)
         #[arg(long)]
         debug: bool,
+        /// Stop an already running noctalia-shell instance before starting
+        #[arg(long)]
+        replace: bool,
+        /// Keep the CLI running until quickshell exits and explain failures
+        #[arg(long)]
+        wait: bool,
+        /// Set an environment variable for quickshell (repeatable)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = run::shell::parse_env)]
+        env: Vec<(String, String)>,
+    },
+    #[command(
+        about = "List components tracked in the CLI config",
+        long_about = "List every component recorded in the CLI config with its installed flag, source and version.",
+        help_template = "Components\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia components\n  noctalia --output json components\n"
+    )]
+    Components,
+    #[command(
+        about = "Show the state of an installed component",
+        long_about = "Show whether a component is installed, its source, version, location and pending updates.",
+        help_template = "Status\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia status\n  noctalia status --component shell\n"
+    )]
+    Status {
+        /// Component to show
+        #[arg(long, value_name = "NAME", default_value = "shell")]
+        component: String,
+    },
+    #[command(
+        about = "Print the installed version of a component",
+        help_template = "Version\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia version\n  noctalia version --component shell\n"
+    )]
+    Version {
+        /// Component to print the version of
+        #[arg(long, value_name = "NAME", default_value = "shell")]
+        component: String,
+    },
+    #[command(
+        about = "Open the installation directory",
+        long_about = "Open the installation directory of a component with xdg-open, or print its path.",
+        help_template = "Open\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia open\n  cd \"$(noctalia open --print)\"\n"
+    )]
+    Open {
+        /// Component whose directory to open
+        #[arg(long, value_name = "NAME", default_value = "shell")]
+        component: String,
+        /// Only print the path
+        #[arg(long)]
+        print: bool,
+    },
+    #[command(
+        about = "List available noctalia-shell releases",
+        long_about = "List recent noctalia-shell releases with their publish date, marking prereleases and the installed one.",
+        help_template = "Releases\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia releases\n  noctalia releases --limit 30\n  noctalia --output json releases\n"
+    )]
+    Releases {
+        /// Number of releases to show (at most 100)
+        #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=100))]
+        limit: u32,
+    },
+    #[command(
+        about = "Check the health of the noctalia setup",
+        long_about = "Run a series of checks on the config, shell installation, quickshell and related services.",
+        help_template = "Doctor\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia doctor\n  noctalia --output json doctor\n"
+    )]
+    Doctor,
+    #[command(
+        about = "Remove all state created by the CLI",
+        long_about = "Remove the CLI config, cached downloads and staged updates. With --all the installed shell is removed as well.",
+        help_template = "Purge\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia purge\n  noctalia purge --all\n  noctalia purge --all --yes\n"
+    )]
+    Purge {
+        /// Also remove the installed noctalia-shell
+        #[arg(long)]
+        all: bool,
+        /// Keep the installed noctalia-shell even with --all
+        #[arg(long)]
+        keep_shell: bool,
+    },
+    #[command(
+        arg_required_else_help = true,
+        about = "Change CLI settings",
+        help_template = "Config\n\nUsage:\n  {usage}\n\nCommands:\n{subcommands}\nOptions:\n{options}\n\nExamples:\n  noctalia config set-source shell git\n  noctalia config set-source shell --unset\n  noctalia --output json config effective\n  noctalia config edit\n"
+    )]
+    Config(ConfigTargets),
+    #[command(
+        about = "Uninstall noctalia-shell",
+        long_about = "Remove the installed noctalia-shell and its systemd user service.",
+        help_template = "Uninstall\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia uninstall\n  noctalia uninstall --keep-service\n"
+    )]
+    Uninstall {
+        /// Component to uninstall
+        #[arg(long, value_name = "NAME", default_value = "shell")]
+        component: String,
+        /// Leave the systemd user service installed
+        #[arg(long)]
+        keep_service: bool,
     },
     #[command(
         about = "IPC commands for noctalia-shell",
         long_about = "Send IPC commands to the running noctalia-shell instance.",
-        help_template = "IPC\n\nUsage:\n  {usage}\n\nExamples:\n  noctalia ipc <target> <function>\n  noctalia ipc show\n"
+        help_template = "IPC\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia ipc <target> <function>\n  noctalia ipc show\n  noctalia ipc show --format json\n  noctalia ipc show --target bar\n  noctalia ipc show --raw\n  noctalia ipc --repeat 5 --interval 500 <target> <function>\n  noctalia ipc <target> <function> [args...]\n  echo '{...}' | noctalia ipc --stdin <target> <function>\n  noctalia ipc batch calls.txt --keep-going\n  noctalia ipc ping\n  noctalia ipc watch <target> <function> [args...]\n"
     )]
     Ipc {
-        /// Target name for the IPC call, or 'show' to list available targets and functions
+        /// Target name for the IPC call, 'show' to list available targets and
+        /// functions, 'batch' to run the calls listed in a file, 'ping' to
+        /// check that the shell responds, or 'watch' to send a call on every
+        /// shell start
         #[arg(value_name = "TARGET")]
         target: String,
-        /// Function name for the IPC call (optional if target is 'show')
+        /// Function name for the IPC call (optional if target is 'show'), or the
+        /// file for 'batch' ('-' for stdin)
         #[arg(value_name = "FUNCTION")]
         function: Option<String>,
+        /// Arguments passed to the IPC function
+        #[arg(value_name = "ARGS", trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+        /// Read one more argument from stdin and pass it after ARGS
+        #[arg(long)]
+        stdin: bool,
+        /// Output format for 'show'
+        #[arg(long, value_enum, default_value_t = ipc::shell::ShowFormat::Tree)]
+        format: ipc::shell::ShowFormat,
+        /// Check the target and function against 'ipc show' before calling (the default)
+        #[arg(long, overrides_with = "no_validate")]
+        validate: bool,
+        /// Skip checking the target and function against 'ipc show' before calling
+        #[arg(long, overrides_with = "validate")]
+        no_validate: bool,
+        /// Number of times to send the call. Give it before TARGET: options after
+        /// FUNCTION are passed to the IPC function as arguments
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        repeat: u32,
+        /// Delay between repeated calls in milliseconds (before TARGET, like --repeat)
+        #[arg(long, value_name = "MS", default_value_t = 0)]
+        interval: u64,
+        /// With 'batch', run the remaining calls after one fails
+        #[arg(long)]
+        keep_going: bool,
+        /// With 'show', only list the functions of this target
+        #[arg(long = "target", value_name = "NAME")]
+        show_target: Option<String>,
+        /// With 'show', print quickshell's output verbatim
+        #[arg(long, conflicts_with_all = ["format", "show_target"])]
+        raw: bool,
     },
 }
 
@@ -74,15 +242,84 @@ enum InstallSub {
     #[command(
         about = "Install the Noctalia shell",
         long_about = "Install the Noctalia shell from either the latest release or git main.",
-        help_template = "Install Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia install shell --release\n  noctalia install shell --git\n"
+        help_template = "Install Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia install shell --release\n  noctalia install shell --git\n  noctalia install shell --branch next\n  noctalia install shell --reinstall\n  noctalia install shell --verify\n  noctalia install shell --from-url https://example.com/noctalia-shell.tar.gz\n"
     )]
-    Shell { #[arg(long)] git: bool, #[arg(long)] release: bool },
+    Shell {
+        #[arg(long)] git: bool,
+        #[arg(long)] release: bool,
+        /// Send a desktop notification when the install finishes
+        #[arg(long)] notify: bool,
+        /// Reinstall even if the requested version is already installed
+        #[arg(long)] force: bool,
+        /// Install into the target of a symlinked installation directory
+        #[arg(long)] follow_symlink: bool,
+        /// Download and cleanly reinstall the recorded version, discarding partial state
+        #[arg(long)] reinstall: bool,
+        /// Fedora: don't enable the quickshell COPR repository (--yes enables it without asking)
+        #[arg(long)] no_copr: bool,
+        /// Arch: install quickshell from the official repositories with pacman, not the AUR
+        #[arg(long)] prefer_system_quickshell: bool,
+        /// Don't check which dependencies are installed; let the package manager skip them
+        #[arg(long)] assume_missing_deps: bool,
+        /// Install packages for this distribution instead of detecting it (also NOCTALIA_DISTRO)
+        #[arg(long, value_enum, value_name = "DISTRO")]
+        force_distro: Option<install::shell::Distribution>,
+        /// Don't print the next steps after a successful install
+        #[arg(long)] no_hints: bool,
+        /// Start the shell for a few seconds after installing to check that it runs (needs a session)
+        #[arg(long)] verify: bool,
+        /// Install from this tarball (http or https) instead of GitHub, e.g. a fork or CI artifact
+        #[arg(long, value_name = "URL", value_parser = http::parse_archive_url, conflicts_with = "reinstall")]
+        from_url: Option<String>,
+        /// Install from a tarball on disk, e.g. one saved with 'noctalia download shell'
+        #[arg(long, value_name = "PATH", conflicts_with = "reinstall")]
+        from_file: Option<PathBuf>,
+        /// Install the head of this git branch
+        #[arg(long, value_name = "NAME", conflicts_with = "reinstall")]
+        branch: Option<String>,
+        /// Install this git commit
+        #[arg(long, value_name = "SHA", conflicts_with = "reinstall")]
+        commit: Option<String>,
+    },
     #[command(
         about = "Install systemd user service for noctalia-shell",
         long_about = "Install the systemd user service to automatically start noctalia-shell on login.",
-        help_template = "Install Systemd Service\n\nUsage:\n  {usage}\n\nExamples:\n  noctalia install systemd\n"
+        help_template = "Install Systemd Service\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia install systemd\n  noctalia install systemd --config-name my-shell\n  noctalia install systemd --unit-dir /etc/systemd/user\n"
     )]
-    Systemd,
+    Systemd {
+        /// Quickshell config name the service should start (qs -c <NAME>)
+        #[arg(long, value_name = "NAME", default_value = install::systemd::DEFAULT_CONFIG_NAME)]
+        config_name: String,
+        /// Install the unit into this directory instead of the detected one (remembered)
+        #[arg(long, value_name = "DIR")]
+        unit_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct DownloadTargets {
+    #[command(subcommand)]
+    target: DownloadSub,
+}
+
+#[derive(Subcommand, Debug)]
+enum DownloadSub {
+    #[command(
+        about = "Download the Noctalia shell tarball",
+        long_about = "Download the shell tarball and print its path and SHA-256, leaving the installation and CLI config untouched.",
+        help_template = "Download Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia download shell\n  noctalia download shell --git --output-dir ~/archive\n  noctalia download shell --tag v2.1.0\n"
+    )]
+    Shell {
+        #[arg(long)] git: bool,
+        #[arg(long)] release: bool,
+        /// Download this release tag instead of the latest
+        #[arg(long, value_name = "TAG")]
+        tag: Option<String>,
+        /// Directory to save the tarball in (default: the cache directory)
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -92,99 +329,290 @@ struct UpdateTargets {
     target: UpdateSub,
 }
 
-
 #[derive(Subcommand, Debug)]
 enum UpdateSub {
     #[command(
         about = "Update the Noctalia shell",
-        help_template = "Update Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia update shell --release\n  noctalia update shell --git\n"
+        help_template = "Update Shell\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia update shell --release\n  noctalia update shell --git\n  noctalia update shell --download-only\n  noctalia update shell --apply-staged\n  noctalia update shell --max-age 6h\n  noctalia update shell --to v2.1.0\n  noctalia update shell --interactive\n  noctalia update shell --refresh-service\n"
     )]
-    Shell { #[arg(long)] git: bool, #[arg(long)] release: bool },
+    Shell {
+        #[arg(long)] git: bool,
+        #[arg(long)] release: bool,
+        /// Send a desktop notification when the update finishes
+        #[arg(long)] notify: bool,
+        /// Download the update into the cache without applying it
+        #[arg(long, conflicts_with = "apply_staged")] download_only: bool,
+        /// Apply a previously downloaded update without touching the network
+        #[arg(long, conflicts_with_all = ["git", "release"])] apply_staged: bool,
+        /// Skip the network check if the last one is younger than this (e.g. 30m, 6h, 2d)
+        #[arg(long, value_name = "DURATION", value_parser = update::parse_duration)]
+        max_age: Option<std::time::Duration>,
+        /// Always check GitHub for updates
+        #[arg(long)] force: bool,
+        /// Update even if the installation directory is a symlink (writes into its target)
+        #[arg(long)] follow_symlink: bool,
+        /// List the commits pulled in by a git update
+        #[arg(long)] changelog: bool,
+        /// Move to a specific release tag, upgrading or downgrading
+        #[arg(long, value_name = "TAG", conflicts_with_all = ["apply_staged", "max_age"])]
+        to: Option<String>,
+        /// Pick the release to move to from a list of recent ones
+        #[arg(long, conflicts_with_all = ["git", "apply_staged", "max_age", "to"])]
+        interactive: bool,
+        /// Re-copy the systemd unit from the updated shell without asking
+        #[arg(long, conflicts_with = "download_only")]
+        refresh_service: bool,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct ConfigTargets {
+    #[command(subcommand)]
+    command: ConfigSub,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigSub {
+    #[command(
+        about = "Print one recorded value, for scripts (exit 3 when unset)",
+        help_template = "Get\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia config get shell.version\n  noctalia config get shell.installed\n  noctalia config get shell.default_source || echo unset\n"
+    )]
+    Get {
+        /// Dotted key: <component>.<field>, e.g. shell.source
+        key: String,
+    },
+    #[command(
+        about = "Set the source used when neither --git nor --release is given",
+        help_template = "Set Source\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia config set-source shell release\n  noctalia config set-source shell git\n  noctalia config set-source shell --unset\n"
+    )]
+    SetSource {
+        /// Component to configure (e.g. shell)
+        component: String,
+        /// Default source for the component
+        #[arg(value_enum, required_unless_present = "unset")]
+        source: Option<SourceKind>,
+        /// Forget the default and ask again on the next install or update
+        #[arg(long, conflicts_with = "source")]
+        unset: bool,
+    },
+    #[command(
+        about = "Show every setting as resolved from the config file, environment and flags",
+        help_template = "Effective\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia config effective\n  NOCTALIA_API_BASE=https://mirror.example noctalia --output json config effective\n"
+    )]
+    Effective,
+    #[command(
+        about = "Open the CLI config in $EDITOR and check it afterwards",
+        help_template = "Edit\n\nUsage:\n  {usage}\n\nOptions:\n{options}\n\nExamples:\n  noctalia config edit\n  EDITOR='code --wait' noctalia config edit\n"
+    )]
+    Edit,
 }
 
 fn main() {
     let cli = Cli::parse();
+    ui::init(ui::Settings {
+        output: cli.output,
+        verbose: cli.verbose,
+        yes: cli.yes,
+        plain: cli.plain,
+        quiet: cli.quiet,
+    });
+    http::init(http::Settings { limit_rate: cli.limit_rate, prefer_ipv4: cli.prefer_ipv4, insecure: cli.insecure, cacert: cli.cacert });
+    if let Some(dir) = cli.cache_dir {
+        config::set_cache_dir(dir);
+    }
+    if let Some(command) = cli.sudo_command {
+        privilege::set_command(command);
+    }
+    if cli.plain {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
 
-    match cli.command {
+    interrupt::install_handler();
+    let started = Instant::now();
+    if let Err(err) = dispatch(cli.command) {
+        ui::report_error(&err);
+        ui::elapsed(started.elapsed(), false);
+        // A step that failed because of Ctrl-C (say a package manager that
+        // got the signal too) still counts as interrupted
+        let code = if interrupt::requested() { ExitCode::Interrupted } else { err.code };
+        std::process::exit(code.code());
+    }
+    ui::elapsed(started.elapsed(), true);
+}
+
+fn dispatch(command: Commands) -> CliResult {
+    // `config` must keep working so a broken bundle setting can be fixed
+    if !matches!(command, Commands::Config(_)) {
+        http::load_ca_bundle()?;
+    }
+    match command {
         Commands::Install(InstallTargets { target }) => {
+            let _lock = lock::acquire()?;
             let (cfg, _path) = config::CliConfig::load().expect("load config");
             match target {
-                InstallSub::Shell { git, release } => {
-                    let resolved = resolve_source("shell", git, release, &cfg);
-                    install::shell::run(resolved);
-                }
-                InstallSub::Systemd => {
-                    install::systemd::run();
+                InstallSub::Shell {
+                    git,
+                    release,
+                    notify,
+                    force,
+                    follow_symlink,
+                    reinstall,
+                    no_copr,
+                    prefer_system_quickshell,
+                    assume_missing_deps,
+                    force_distro,
+                    no_hints,
+                    verify,
+                    from_url,
+                    from_file,
+                    branch,
+                    commit,
+                } => {
+                    let flags = config::SourceFlags { git, release, branch, commit, url: from_url, file: from_file, ..Default::default() };
+                    let spec = config::SourceSpec::from_flags(flags).map_err(CliError::usage)?;
+                    let spec = config::resolve_source("shell", spec, &cfg)?;
+                    let deps = install::shell::DependencyOptions {
+                        no_copr,
+                        prefer_system_quickshell,
+                        assume_missing: assume_missing_deps,
+                        distro: install::shell::distro_override(force_distro)?,
+                    };
+                    let result = install::shell::run(
+                        spec,
+                        force,
+                        follow_symlink,
+                        reinstall,
+                        deps,
+                        install::shell::AfterInstall { hints: !no_hints, verify },
+                        &mut progress::TerminalSink,
+                    );
+                    if notify || cfg.notify.enabled {
+                        notify::report("install", "shell", &result);
+                    }
+                    result
                 }
+                InstallSub::Systemd { config_name, unit_dir } => install::systemd::run(&config_name, unit_dir.as_deref()),
             }
         }
         Commands::Update(UpdateTargets { target }) => {
+            let _lock = lock::acquire()?;
             let (cfg, _path) = config::CliConfig::load().expect("load config");
             match target {
-                UpdateSub::Shell { git, release } => {
-                    let resolved = resolve_source("shell", git, release, &cfg);
-                    update::shell::run(resolved);
+                UpdateSub::Shell {
+                    git,
+                    release,
+                    notify,
+                    download_only,
+                    apply_staged,
+                    max_age,
+                    force,
+                    follow_symlink,
+                    changelog,
+                    to,
+                    interactive,
+                    refresh_service,
+                } => {
+                    let result = if apply_staged {
+                        update::shell::apply_staged(follow_symlink, &mut progress::TerminalSink)
+                    } else {
+                        let to = if interactive { update::shell::pick_release()? } else { to };
+                        let flags = config::SourceFlags { git, release, tag: to, ..Default::default() };
+                        let spec = config::SourceSpec::from_flags(flags).map_err(CliError::usage)?;
+                        let spec = config::resolve_update_source("shell", spec, &cfg)?;
+                        let opts = update::shell::Options {
+                            download_only,
+                            force,
+                            follow_symlink,
+                            max_age,
+                            changelog: changelog || cfg.update.changelog,
+                        };
+                        update::shell::run(spec, opts, &mut progress::TerminalSink)
+                    };
+                    // The shell may ship a changed unit; a staged download has not changed anything yet
+                    let result = result.and_then(|_| {
+                        if download_only { Ok(()) } else { install::systemd::refresh(refresh_service) }
+                    });
+                    if notify || cfg.notify.enabled {
+                        notify::report("update", "shell", &result);
+                    }
+                    result
                 }
             }
         }
-        Commands::Run { debug } => {
-            run::shell::run(debug);
+        Commands::Download(DownloadTargets { target }) => match target {
+            DownloadSub::Shell { git, release, tag, output_dir } => {
+                let flags = config::SourceFlags { git, release, tag, ..Default::default() };
+                let spec = config::SourceSpec::from_flags(flags).map_err(CliError::usage)?;
+                // Unlike install, never prompt or remember a choice here
+                let (cfg, _path) = config::CliConfig::load().expect("load config");
+                let spec = spec.unwrap_or_else(|| {
+                    cfg.get_default_source("shell")
+                        .or_else(|| cfg.get_component_source("shell"))
+                        .unwrap_or_default()
+                        .into()
+                });
+                download::run(spec, output_dir.as_deref())
+            }
+        },
+        Commands::Run { debug, replace, wait, env } => run::shell::run(debug, replace, wait, env),
+        Commands::Components => components::run(),
+        Commands::Status { component } => status::run(&component),
+        Commands::Version { component } => version::run(&component),
+        Commands::Open { component, print } => open::run(&component, print),
+        Commands::Releases { limit } => releases::run(limit),
+        Commands::Doctor => doctor::run(),
+        Commands::Purge { all, keep_shell } => purge::run(all && !keep_shell),
+        Commands::Config(ConfigTargets { command }) => match command {
+            ConfigSub::Get { key } => config::command::get(&key),
+            ConfigSub::SetSource { component, source, .. } => config::command::set_source(&component, source),
+            ConfigSub::Effective => config::command::effective(),
+            ConfigSub::Edit => config::command::edit(),
+        },
+        Commands::Uninstall { component, keep_service } => {
+            let _lock = lock::acquire()?;
+            uninstall::run(&component, keep_service)
         }
-        Commands::Ipc { target, function } => {
+        Commands::Ipc { target, function, args, stdin, format, validate, no_validate, repeat, interval, keep_going, show_target, raw } => {
+            // The later of --validate and --no-validate wins
+            let validate = validate || !no_validate;
             if target == "show" {
-                ipc::shell::run_show();
+                ipc::shell::run_show(format, show_target.as_deref(), raw)
+            } else if target == "ping" {
+                ipc::shell::run_ping()
+            } else if target == "watch" {
+                // `watch <target> <function> [args...]` shifts everything by one
+                let mut args = args.into_iter();
+                match (function, args.next()) {
+                    (Some(target), Some(func)) => ipc::shell::run_watch(target, func, args.collect(), validate),
+                    _ => Err(CliError::usage("A target and function are required for 'ipc watch'.")
+                        .with_hint("Usage: noctalia ipc watch <target> <function> [args...]")),
+                }
+            } else if target == "batch" {
+                // Options after FILE land in the trailing ARGS
+                let keep_going = keep_going || args.iter().any(|a| a == "--keep-going");
+                let validate = match args.iter().rev().find(|a| *a == "--validate" || *a == "--no-validate") {
+                    Some(flag) => flag == "--validate",
+                    None => validate,
+                };
+                if let Some(extra) = args.iter().find(|a| !["--keep-going", "--validate", "--no-validate"].contains(&a.as_str())) {
+                    return Err(CliError::usage(format!("Unexpected argument '{}' for 'ipc batch'", extra))
+                        .with_hint("Usage: noctalia ipc batch <FILE> [--keep-going]"));
+                }
+                match function {
+                    Some(file) => ipc::shell::run_batch(&file, validate, keep_going),
+                    None => Err(CliError::usage("A batch file is required.")
+                        .with_hint("Usage: noctalia ipc batch <FILE> [--keep-going]")),
+                }
             } else {
                 match function {
-                    Some(func) => {
-                        ipc::shell::run_call(target, func);
-                    }
-                    None => {
-                        eprintln!("Error: Function name is required when making an IPC call.");
-                        eprintln!("Usage: noctalia ipc <target> <function>");
-                        eprintln!("       noctalia ipc show");
-                        std::process::exit(1);
-                    }
+                    Some(func) => ipc::shell::run_call(target, func, args, stdin, validate, repeat, interval),
+                    None => Err(CliError::failure("Function name is required when making an IPC call.")
+                        .with_hint("Usage: noctalia ipc <target> <function>")
+                        .with_hint("       noctalia ipc show")),
                 }
             }
         }
     }
 }
-
-fn resolve_source(component: &str, git: bool, release: bool, cfg: &config::CliConfig) -> SourceKind {
-    if git && release {
-        eprintln!("Both --git and --release provided; please specify only one.");
-        std::process::exit(2);
-    }
-    if git { return SourceKind::Git; }
-    if release { return SourceKind::Release; }
-
-    if let Some(saved) = cfg.get_component_source(component) {
-        return saved;
-    }
-
-    prompt_and_persist_choice(component)
-}
-
-fn prompt_and_persist_choice(component: &str) -> SourceKind {
-    use dialoguer::{theme::ColorfulTheme, Select};
-    let (mut cfg, path) = config::CliConfig::load().expect("load config");
-    let items = ["release", "git"];
-    let theme = ColorfulTheme::default();
-    let selection = Select::with_theme(&theme)
-        .with_prompt(format!("Choose source for {}", component))
-        .default(0)
-        .items(&items)
-        .interact_opt();
-
-    let chosen = match selection {
-        Ok(Some(idx)) => if idx == 1 { SourceKind::Git } else { SourceKind::Release },
-        _ => {
-            // Non-interactive or error: default to release
-            SourceKind::Release
-        }
-    };
-
-    cfg.set_component_source(component, chosen);
-    let _ = cfg.save(&path);
-    chosen
-}
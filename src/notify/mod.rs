@@ -0,0 +1,36 @@
+use std::process::{Command, Stdio};
+
+use crate::config;
+use crate::error::CliResult;
+use crate::update::shell::display_version;
+
+pub fn send(summary: &str, body: &str) {
+    // Best effort: no notification daemon or missing notify-send is not an error
+    let _ = Command::new("notify-send")
+        .args(["--app-name=Noctalia", summary, body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+pub fn report(action: &str, component: &str, result: &CliResult) {
+    match result {
+        Ok(()) => {
+            let version = config::CliConfig::load()
+                .ok()
+                .and_then(|(cfg, _)| {
+                    let source = cfg.get_component_source(component).unwrap_or_default();
+                    cfg.get_component_version(component).map(|v| display_version(source, &v))
+                })
+                .unwrap_or_else(|| "unknown version".to_string());
+            send(
+                &format!("Noctalia {} {} complete", component, action),
+                &format!("noctalia-{} is at {}", component, version),
+            );
+        }
+        Err(err) => {
+            send(&format!("Noctalia {} {} failed", component, action), &err.message);
+        }
+    }
+}
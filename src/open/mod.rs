@@ -0,0 +1,35 @@
+use std::process::{Command, Stdio};
+
+use crate::config;
+use crate::error::{CliError, CliResult, ExitCode};
+use crate::ui;
+
+/// Show the installation directory of `component` and open it with
+/// `xdg-open`; with `print_only`, just print the path for use in scripts.
+pub fn run(component: &str, print_only: bool) -> CliResult {
+    config::check_component(component)?;
+    let Some(path) = config::find_shell_path() else {
+        return Err(CliError::new(ExitCode::NotFound, format!("No {} installation found", component))
+            .with_hint(format!("Install it with: noctalia install {}", component)));
+    };
+
+    if print_only {
+        println!("{}", path.display());
+        return Ok(());
+    }
+
+    ui::info(&format!("Opening {}", path.display()));
+    let status = Command::new("xdg-open")
+        .arg(&path)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| {
+            CliError::failure(format!("Failed to run xdg-open: {}", e))
+                .with_hint("Use 'noctalia open --print' to only print the path.")
+        })?;
+    if !status.success() {
+        return Err(CliError::failure(format!("xdg-open exited with {}", status))
+            .with_hint("Use 'noctalia open --print' to only print the path."));
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use serde::Serialize;
+
+use crate::error::{CliError, CliResult};
+
+/// Version of the JSON shapes below, printed as `schema_version` at the top
+/// of every JSON document. Bump it when a field is removed, renamed or
+/// changes type; adding a field is not a breaking change.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Versioned<'a, T: Serialize> {
+    schema_version: u32,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+/// Print `body` as pretty JSON on stdout, tagged with the schema version
+pub fn print<T: Serialize>(body: &T) -> CliResult {
+    let out = serde_json::to_string_pretty(&Versioned { schema_version: SCHEMA_VERSION, body })
+        .map_err(|e| CliError::failure(format!("Failed to serialize output: {}", e)))?;
+    println!("{}", out);
+    Ok(())
+}
+
+/// Print `rows` under `header` as left-aligned columns on stdout, the text
+/// counterpart of `print`. The last column is not padded.
+pub fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: [&str; N]| {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i + 1 == N {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:<width$}  ", cell, width = widths[i]));
+            }
+        }
+        println!("{}", line.trim_end());
+    };
+    line(header);
+    for row in rows {
+        line(row.each_ref().map(String::as_str));
+    }
+}
+
+/// `--output json` error report, printed on stderr
+#[derive(Debug, Serialize)]
+pub struct ErrorReport<'a> {
+    pub schema_version: u32,
+    pub error: ErrorBody<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody<'a> {
+    pub code: i32,
+    pub message: &'a str,
+}
+
+/// `noctalia --output json components`
+#[derive(Debug, Serialize)]
+pub struct Components {
+    pub components: Vec<ComponentRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentRow {
+    pub name: String,
+    pub installed: bool,
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_source: Option<String>,
+    pub version: Option<String>,
+    pub commit: Option<String>,
+}
+
+/// `noctalia --output json releases`
+#[derive(Debug, Serialize)]
+pub struct Releases {
+    pub releases: Vec<ReleaseRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReleaseRow {
+    pub tag: String,
+    pub published_at: Option<String>,
+    pub prerelease: bool,
+    pub installed: bool,
+}
+
+/// `noctalia --output json config effective`
+#[derive(Debug, Serialize)]
+pub struct Settings {
+    pub settings: Vec<SettingRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SettingRow {
+    pub key: String,
+    pub value: String,
+    /// `default`, `config`, `flag` or `env NAME`
+    pub source: String,
+}
+
+/// `noctalia --output json doctor`
+#[derive(Debug, Serialize)]
+pub struct DoctorReport<'a> {
+    pub healthy: bool,
+    pub checks: &'a [Check],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(check: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Check { check, status, detail: detail.into() }
+    }
+}
+
+/// `noctalia --output json status`
+#[derive(Debug, Default, Serialize)]
+pub struct StatusReport {
+    pub component: String,
+    pub installed: bool,
+    pub source: Option<String>,
+    pub default_source: Option<String>,
+    pub version: Option<String>,
+    pub commit: Option<String>,
+    pub path: Option<String>,
+    pub files: Option<FilesState>,
+    pub running: bool,
+    pub staged_update: Option<String>,
+    pub last_check: Option<LastCheck>,
+}
+
+/// The installed files compared with the fingerprint taken at install time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilesState {
+    Unchanged,
+    Modified,
+    /// Installed before fingerprinting
+    Untracked,
+    /// The files could not be read
+    Unknown,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastCheck {
+    /// Unix time in seconds
+    pub checked_at: u64,
+    pub source: String,
+    pub latest: String,
+}
+
+/// `noctalia --output json version`
+#[derive(Debug, Serialize)]
+pub struct VersionReport {
+    pub component: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub commit: Option<String>,
+}
+
+/// `noctalia --output json install shell`, printed once the install succeeds
+#[derive(Debug, Serialize)]
+pub struct InstallResult {
+    pub component: String,
+    pub source: String,
+    pub version: String,
+    pub commit: Option<String>,
+    pub path: String,
+    /// False when the requested version was already installed
+    pub changed: bool,
+}
+
+/// `noctalia ipc show --format json`
+#[derive(Debug, Serialize)]
+pub struct IpcTargets<'a> {
+    pub targets: &'a [IpcTarget],
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpcTarget {
+    pub name: String,
+    pub functions: Vec<String>,
+}
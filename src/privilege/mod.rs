@@ -0,0 +1,220 @@
+use std::{
+    env,
+    path::Path,
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use crate::error::{CliError, CliResult};
+use crate::ui;
+
+/// `--sudo-command`, set once at startup
+static OVERRIDE: OnceLock<String> = OnceLock::new();
+/// The detected tool, resolved on first use
+static DETECTED: OnceLock<Result<Escalation, String>> = OnceLock::new();
+
+pub fn set_command(command: String) {
+    let _ = OVERRIDE.set(command);
+}
+
+/// How commands that need root are run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Escalation {
+    /// Already root: run commands as they are
+    Root,
+    Sudo,
+    Doas,
+    Pkexec,
+    /// `--sudo-command` with arguments or an unknown tool, split into words
+    Custom(Vec<String>),
+}
+
+fn on_path(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+/// A known tool called `program` (by name or path), if it is one
+fn known(program: &str) -> Option<Escalation> {
+    match Path::new(program).file_name()?.to_str()? {
+        "sudo" => Some(Escalation::Sudo),
+        "doas" => Some(Escalation::Doas),
+        "pkexec" => Some(Escalation::Pkexec),
+        _ => None,
+    }
+}
+
+fn detect() -> Result<Escalation, String> {
+    if let Some(command) = OVERRIDE.get() {
+        let words = shell_words::split(command).map_err(|e| format!("cannot parse --sudo-command '{}': {}", command, e))?;
+        let Some(program) = words.first() else {
+            return Err("--sudo-command is empty".to_string());
+        };
+        if !on_path(program) {
+            return Err(format!("--sudo-command '{}' not found", program));
+        }
+        // A bare `sudo` or `/usr/bin/doas` still gets the tool's own handling
+        return Ok(match known(program) {
+            Some(tool) if words.len() == 1 && !program.contains('/') => tool,
+            _ => Escalation::Custom(words),
+        });
+    }
+    if users::get_effective_uid() == 0 {
+        return Ok(Escalation::Root);
+    }
+    ["sudo", "doas", "pkexec"]
+        .into_iter()
+        .find(|tool| on_path(tool))
+        .and_then(known)
+        .ok_or_else(|| "no privilege escalation tool found (install sudo or doas)".to_string())
+}
+
+impl Escalation {
+    /// `--sudo-command` if given, else root needs nothing, else the first of
+    /// sudo, doas and pkexec on `PATH`. Detected once; fails before anything
+    /// is run when there is no way to elevate.
+    pub fn detect() -> CliResult<Escalation> {
+        DETECTED.get_or_init(detect).clone().map_err(|e| {
+            CliError::failure(e).with_hint("Pass --sudo-command to name the tool to use, e.g. --sudo-command doas.")
+        })
+    }
+
+    /// Name of the tool for messages, e.g. `doas`
+    pub fn name(&self) -> &str {
+        match self {
+            Escalation::Root => "root",
+            Escalation::Sudo => "sudo",
+            Escalation::Doas => "doas",
+            Escalation::Pkexec => "pkexec",
+            Escalation::Custom(words) => &words[0],
+        }
+    }
+
+    /// The words put in front of an elevated command
+    fn prefix(&self) -> Vec<&str> {
+        match self {
+            Escalation::Root => Vec::new(),
+            Escalation::Custom(words) => words.iter().map(String::as_str).collect(),
+            tool => vec![tool.name()],
+        }
+    }
+
+    /// `program` run as root
+    pub fn command(&self, program: &str) -> Command {
+        match self.prefix().split_first() {
+            Some((tool, args)) => {
+                let mut cmd = Command::new(tool);
+                cmd.args(args).arg(program);
+                cmd
+            }
+            None => Command::new(program),
+        }
+    }
+
+    /// `command` as the user would type it to run it as root
+    pub fn shown(&self, command: &str) -> String {
+        self.prefix().into_iter().chain([command]).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Tell the user a password prompt may follow
+    pub fn announce(&self) {
+        if *self != Escalation::Root {
+            ui::info(&format!("Elevating with {}. You may be prompted for your password.", self.name()));
+        }
+    }
+
+    /// Run `args` as root with the terminal attached, failing on a non-zero exit
+    pub fn run(&self, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        let (program, rest) = args.split_first().ok_or("no command to run")?;
+        let status = self
+            .command(program)
+            .args(rest)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()?;
+        if !status.success() {
+            return Err(format!("'{}' failed", self.shown(&args.join(" "))).into());
+        }
+        Ok(())
+    }
+
+    /// Options for yay and paru, which elevate pacman themselves and call
+    /// `sudo` unless told otherwise with `--sudo`/`--sudoflags`
+    pub fn aur_helper_args(&self) -> Vec<String> {
+        match self {
+            Escalation::Root | Escalation::Sudo => Vec::new(),
+            Escalation::Doas | Escalation::Pkexec => vec!["--sudo".to_string(), self.name().to_string()],
+            Escalation::Custom(words) => {
+                let mut args = vec!["--sudo".to_string(), words[0].clone()];
+                if words.len() > 1 {
+                    args.extend(["--sudoflags".to_string(), shell_words::join(&words[1..])]);
+                }
+                args
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn command_puts_the_tool_in_front() {
+        let custom = Escalation::Custom(vec!["sudo".into(), "-A".into()]);
+        let cases = [
+            (Escalation::Root, vec!["pacman"]),
+            (Escalation::Sudo, vec!["sudo", "pacman"]),
+            (Escalation::Doas, vec!["doas", "pacman"]),
+            (Escalation::Pkexec, vec!["pkexec", "pacman"]),
+            (Escalation::Custom(vec!["run0".into()]), vec!["run0", "pacman"]),
+            (custom, vec!["sudo", "-A", "pacman"]),
+        ];
+        for (escalation, expected) in cases {
+            assert_eq!(argv(&escalation.command("pacman")), expected, "{:?}", escalation);
+        }
+    }
+
+    #[test]
+    fn shown_matches_the_command() {
+        let cases = [
+            (Escalation::Root, "apt install -y qs"),
+            (Escalation::Sudo, "sudo apt install -y qs"),
+            (Escalation::Doas, "doas apt install -y qs"),
+            (Escalation::Pkexec, "pkexec apt install -y qs"),
+            (Escalation::Custom(vec!["run0".into()]), "run0 apt install -y qs"),
+            (Escalation::Custom(vec!["sudo".into(), "-A".into()]), "sudo -A apt install -y qs"),
+        ];
+        for (escalation, expected) in cases {
+            assert_eq!(escalation.shown("apt install -y qs"), expected);
+        }
+    }
+
+    #[test]
+    fn aur_helper_args_name_the_tool_unless_sudo() {
+        let cases: [(Escalation, &[&str]); 6] = [
+            (Escalation::Root, &[]),
+            (Escalation::Sudo, &[]),
+            (Escalation::Doas, &["--sudo", "doas"]),
+            (Escalation::Pkexec, &["--sudo", "pkexec"]),
+            (Escalation::Custom(vec!["run0".into()]), &["--sudo", "run0"]),
+            (
+                Escalation::Custom(vec!["sudo".into(), "-A".into(), "--prompt=pw: x".into()]),
+                &["--sudo", "sudo", "--sudoflags", "-A '--prompt=pw: x'"],
+            ),
+        ];
+        for (escalation, expected) in cases {
+            assert_eq!(escalation.aur_helper_args(), expected, "{:?}", escalation);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Progress reporting for install and update, so the flows can be driven by
+//! something other than the terminal (e.g. a settings panel).
+
+use crate::error::CliResult;
+use crate::ui;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Success,
+    Warn,
+    Error,
+}
+
+/// Receives what an install or update is doing
+pub trait ProgressSink {
+    /// A new phase started (resolving, downloading, extracting, ...)
+    fn phase(&mut self, name: &str);
+    /// Download progress; `total` is `None` when the size is unknown
+    fn bytes(&mut self, done: u64, total: Option<u64>);
+    fn message(&mut self, level: Level, text: &str);
+    /// The flow ended with `result`
+    fn finished(&mut self, result: &CliResult);
+}
+
+/// Prints through `ui`, as the CLI always has
+#[derive(Debug, Default)]
+pub struct TerminalSink;
+
+impl ProgressSink for TerminalSink {
+    fn phase(&mut self, name: &str) {
+        ui::step(name);
+    }
+
+    fn bytes(&mut self, _done: u64, _total: Option<u64>) {}
+
+    fn message(&mut self, level: Level, text: &str) {
+        match level {
+            Level::Info => ui::info(text),
+            Level::Success => ui::success(text),
+            Level::Warn => ui::warn(text),
+            Level::Error => ui::error(text),
+        }
+    }
+
+    // Errors are reported once by main
+    fn finished(&mut self, _result: &CliResult) {}
+}
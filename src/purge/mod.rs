@@ -0,0 +1,70 @@
+use std::{fs, path::Path};
+
+use crate::config;
+use crate::error::{CliError, CliResult};
+use crate::privilege::Escalation;
+use crate::run::shell::is_noctalia_running;
+use crate::ui;
+
+pub(crate) fn remove(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if path.starts_with("/etc") {
+        let path_str = path.to_str().ok_or("path is not valid UTF-8")?;
+        return Escalation::detect()?.run(&["rm", "-rf", path_str]);
+    }
+    // Remove symlinks themselves, never what they point at
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Remove the config file and the cache dir (downloaded tarballs included),
+/// plus the installed shell when `remove_shell` is set.
+pub fn run(remove_shell: bool) -> CliResult {
+    ui::section("Purge Noctalia CLI State");
+
+    let mut paths = vec![config::config_path(), config::cache_dir()];
+    if remove_shell && let Some(shell) = config::find_shell_path() {
+        paths.push(shell);
+    }
+    paths.retain(|p| fs::symlink_metadata(p).is_ok());
+
+    if paths.is_empty() {
+        ui::success("Nothing to purge");
+        return Ok(());
+    }
+
+    ui::info("The following paths will be removed:");
+    for path in &paths {
+        ui::item(&path.display().to_string());
+    }
+    if remove_shell && is_noctalia_running() {
+        ui::warn("noctalia-shell is still running; stop it before reinstalling");
+    }
+    if !ui::confirm(&format!("Remove {} path(s)?", paths.len()), false)? {
+        ui::info("Nothing was removed");
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    for path in &paths {
+        match remove(path) {
+            Ok(()) => ui::success(&format!("Removed {}", path.display())),
+            Err(e) => {
+                ui::error(&format!("Failed to remove {}: {}", path.display(), e));
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(CliError::failure(format!("{} of {} paths could not be removed", failures, paths.len())));
+    }
+    if !remove_shell {
+        ui::info("The installed shell was kept; use --all to remove it too");
+    }
+    Ok(())
+}
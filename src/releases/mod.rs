@@ -0,0 +1,59 @@
+use std::cmp::Ordering;
+
+use crate::SourceKind;
+use crate::config;
+use crate::error::CliResult;
+use crate::http;
+use crate::output::{self, ReleaseRow, Releases};
+use crate::ui;
+use crate::update::shell::compare_tags;
+
+/// List recent noctalia-shell releases, marking the installed one
+pub fn run(limit: u32) -> CliResult {
+    let releases = http::get_releases(limit).map_err(|e| e.into_cli("Failed to fetch releases"))?;
+
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    let installed = cfg
+        .get_component_version("shell")
+        .filter(|_| cfg.is_component_installed("shell") && cfg.get_component_source("shell") == Some(SourceKind::Release));
+
+    let rows: Vec<ReleaseRow> = releases
+        .into_iter()
+        .map(|r| ReleaseRow {
+            installed: installed.as_deref().and_then(|v| compare_tags(v, &r.tag_name)) == Some(Ordering::Equal),
+            tag: r.tag_name,
+            published_at: r.published_at,
+            prerelease: r.prerelease,
+        })
+        .collect();
+
+    if ui::is_json() {
+        return output::print(&Releases { releases: rows });
+    }
+
+    ui::section("Noctalia Shell Releases");
+    if rows.is_empty() {
+        ui::info("No releases found");
+        return Ok(());
+    }
+
+    let table: Vec<[String; 3]> = rows
+        .iter()
+        .map(|row| {
+            // Only the date part of the ISO 8601 timestamp
+            let date = row.published_at.as_deref().map(|d| d.get(..10).unwrap_or(d)).unwrap_or("-");
+            let mut notes = Vec::new();
+            if row.prerelease {
+                notes.push("prerelease");
+            }
+            if row.installed {
+                notes.push("installed");
+            }
+            [row.tag.clone(), date.to_string(), notes.join(", ")]
+        })
+        .collect();
+    output::table(["TAG", "PUBLISHED", "NOTES"], &table);
+    ui::blank();
+    ui::info("Install a specific one with: noctalia update shell --to <TAG>");
+    Ok(())
+}
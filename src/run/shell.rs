@@ -1,16 +1,272 @@
-use std::process::Command;
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{ChildStderr, Command, Stdio},
+    thread,
+    time::Duration,
+};
 
+use crate::archive;
 use crate::config;
+use crate::error::{CliError, CliResult, ExitCode};
 use crate::ui;
 
-pub fn run(debug: bool) {
+pub fn is_noctalia_running() -> bool {
+    // Check if quickshell is running with noctalia-shell
+    // We check for processes that match "qs" and contain "noctalia-shell"
+    let output = Command::new("pgrep")
+        .args(["-f", "qs.*noctalia-shell"])
+        .output();
+    
+    match output {
+        Ok(output) => output.status.success(),
+        Err(_) => {
+            // If pgrep fails, try using ps as fallback
+            let ps_output = Command::new("ps")
+                .args(["-eo", "cmd"])
+                .output();
+            
+            match ps_output {
+                Ok(ps_output) => {
+                    let stdout = String::from_utf8_lossy(&ps_output.stdout);
+                    stdout.lines().any(|line| {
+                        line.contains("qs") && line.contains("noctalia-shell")
+                    })
+                }
+                Err(_) => false,
+            }
+        }
+    }
+}
+
+/// Directories quickshell searches for `qs -c <name>`, in order:
+/// `$XDG_CONFIG_HOME` (default `~/.config`), then each of `$XDG_CONFIG_DIRS`
+/// (default `/etc/xdg`), with `quickshell` appended
+fn quickshell_config_dirs() -> Vec<PathBuf> {
+    let non_empty = |var: &str| env::var(var).ok().filter(|v| !v.is_empty());
+    let home = non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let system = non_empty("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".to_string());
+    home.into_iter()
+        .chain(system.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .map(|dir| dir.join("quickshell"))
+        .collect()
+}
+
+/// Fail with guidance when `qs -c noctalia-shell` would not find the
+/// installed config, e.g. because `$XDG_CONFIG_HOME` points elsewhere
+pub fn check_config_resolvable() -> CliResult {
+    let dirs = quickshell_config_dirs();
+    if dirs.iter().any(|dir| dir.join("noctalia-shell").join(archive::ENTRY_POINT).is_file()) {
+        return Ok(());
+    }
+
+    let mut err = CliError::failure("quickshell cannot find the noctalia-shell config");
+    if let Some(installed) = config::find_shell_path() {
+        err = err.with_hint(format!("It is installed at {}", installed.display()));
+    }
+    let searched: Vec<String> = dirs.iter().map(|dir| dir.join("noctalia-shell").display().to_string()).collect();
+    err = err.with_hint(format!("qs -c noctalia-shell looks in: {}", searched.join(", ")));
+    if let Ok(xdg) = env::var("XDG_CONFIG_HOME")
+        && !xdg.is_empty()
+    {
+        err = err.with_hint(format!(
+            "XDG_CONFIG_HOME is {}, so quickshell does not look in ~/.config; unset it or link the installation to {}/quickshell/noctalia-shell.",
+            xdg, xdg
+        ));
+    }
+    Err(err)
+}
+
+fn wait_for_exit(timeout: Duration) -> bool {
+    let interval = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    while waited < timeout {
+        if !is_noctalia_running() {
+            return true;
+        }
+        thread::sleep(interval);
+        waited += interval;
+    }
+    !is_noctalia_running()
+}
+
+fn signal_instances(signal: &str) -> Result<(), String> {
+    Command::new("pkill")
+        .args([signal, "-f", "qs.*noctalia-shell"])
+        .status()
+        .map(|_| ())
+        .map_err(|e| format!("failed to run pkill: {}", e))
+}
+
+pub fn stop_running_instance() -> Result<(), String> {
+    // Ask nicely first, then force it if the shell doesn't go away
+    signal_instances("-TERM")?;
+    if wait_for_exit(Duration::from_secs(3)) {
+        return Ok(());
+    }
+
+    ui::warn("Instance did not exit after SIGTERM, sending SIGKILL");
+    signal_instances("-KILL")?;
+    if wait_for_exit(Duration::from_secs(2)) {
+        Ok(())
+    } else {
+        Err("noctalia-shell is still running after SIGKILL".to_string())
+    }
+}
+
+/// Number of quickshell stderr lines kept for the failure summary
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Forward quickshell's stderr to ours as it arrives (unless `forward` is
+/// false), keeping the last lines
+fn tee_stderr(stderr: ChildStderr, forward: bool) -> thread::JoinHandle<VecDeque<String>> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            if forward {
+                let _ = io::stderr().write_all(&line);
+            }
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            line.clear();
+        }
+        tail
+    })
+}
+
+/// Add the likely causes of a failure to `err` as hints, or quickshell's last
+/// message if none is recognized
+fn report_likely_causes(mut err: CliError, tail: &VecDeque<String>) -> CliError {
+    let causes = likely_causes(tail);
+    if causes.is_empty()
+        && let Some(last) = tail.iter().rev().find(|l| !l.is_empty())
+    {
+        err = err.with_hint(format!("Last quickshell message: {}", last));
+    }
+    for hint in causes {
+        err = err.with_hint(hint);
+    }
+    err
+}
+
+/// Guess why quickshell failed from the end of its stderr
+fn likely_causes(tail: &VecDeque<String>) -> Vec<String> {
+    let mut hints = Vec::new();
+    let has = |needle: &str| tail.iter().any(|l| l.to_lowercase().contains(needle));
+
+    if has("brightnessctl") {
+        hints.push("brightnessctl seems to be missing; install it or re-run 'noctalia install shell'.".to_string());
+    }
+    if has("module") && has("is not installed") {
+        hints.push("A QML module is missing; check that quickshell and its Qt dependencies are installed.".to_string());
+    }
+    if has("no such config") || has("could not find config") || has("config not found") {
+        hints.push("quickshell could not find the noctalia-shell config; try 'noctalia install shell --reinstall'.".to_string());
+    }
+    if has("segmentation fault") || has("core dumped") {
+        hints.push("quickshell crashed; please report it with the output above.".to_string());
+    }
+    hints
+}
+
+/// How long `verify_starts` watches quickshell for an early exit
+const VERIFY_WINDOW: Duration = Duration::from_secs(5);
+
+/// Start the shell briefly to check that it comes up, then stop it again.
+/// Quickshell's output is only shown under --verbose; on failure the error
+/// carries the likely causes from its stderr.
+pub fn verify_starts() -> CliResult {
+    if is_noctalia_running() {
+        ui::info("noctalia-shell is already running; restart it to use the new files");
+        return Ok(());
+    }
+
+    let mut child = Command::new("qs")
+        .args(["-c", "noctalia-shell"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            CliError::failure(format!("Failed to start noctalia-shell: {}", e))
+                .with_hint("Make sure 'qs' (quickshell) is installed and available in your PATH.")
+        })?;
+    let tee = child.stderr.take().map(|stderr| tee_stderr(stderr, ui::is_verbose()));
+
+    let interval = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    let exited = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if waited < VERIFY_WINDOW => {
+                thread::sleep(interval);
+                waited += interval;
+            }
+            _ => break None,
+        }
+    };
+
+    let Some(status) = exited else {
+        // Still up after the window: it starts. Stop it the same way --replace does.
+        if let Err(e) = stop_running_instance() {
+            ui::warn(&format!("Could not stop the test instance: {}", e));
+            let _ = child.kill();
+        }
+        let _ = child.wait();
+        return Ok(());
+    };
+
+    let tail = tee.and_then(|t| t.join().ok()).unwrap_or_default();
+    let err = CliError::failure(format!("noctalia-shell exited right after starting ({})", status));
+    Err(report_likely_causes(err, &tail))
+}
+
+/// Parse a `--env KEY=VALUE` argument
+pub fn parse_env(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))?;
+    if key.is_empty() {
+        return Err(format!("missing variable name in '{}'", s));
+    }
+    if key.chars().any(|c| c.is_whitespace() || c == '\0') || value.contains('\0') {
+        return Err(format!("invalid environment variable '{}'", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Start the shell. By default the CLI process is replaced by quickshell, so
+/// signals reach it directly; with `wait` it runs as a child and failures
+/// are summarized once it exits.
+pub fn run(debug: bool, replace: bool, wait: bool, env: Vec<(String, String)>) -> CliResult {
     ui::section("Run Noctalia Shell");
     
     // Check if shell is installed
     let (cfg, _path) = config::CliConfig::load().expect("load config");
     if !cfg.is_component_installed("shell") {
-        ui::error("Noctalia shell is not installed. Run 'noctalia install shell' first.");
-        std::process::exit(1);
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
+    }
+
+    check_config_resolvable()?;
+
+    // Avoid starting a second instance on top of a running one
+    if is_noctalia_running() {
+        if !replace {
+            return Err(CliError::failure("Noctalia shell is already running.")
+                .with_hint("Use 'noctalia run --replace' to stop the running instance and start a new one."));
+        }
+
+        ui::step("Stopping running noctalia-shell instance");
+        stop_running_instance()
+            .map_err(|e| CliError::failure(format!("Failed to stop running instance: {}", e)))?;
+        ui::success("Stopped running instance");
     }
 
     if debug {
@@ -21,30 +277,48 @@ pub fn run(debug: bool) {
     
     // Execute qs -c noctalia-shell
     let mut cmd = Command::new("qs");
-    cmd.arg("-c")
-        .arg("noctalia-shell")
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit());
-    
+    cmd.arg("-c").arg("noctalia-shell");
+
     // Set NOCTALIA_DEBUG=1 if debug flag is enabled
     if debug {
         cmd.env("NOCTALIA_DEBUG", "1");
     }
-    
-    let status = cmd.status();
 
-    match status {
-        Ok(exit_status) => {
-            if !exit_status.success() {
-                std::process::exit(exit_status.code().unwrap_or(1));
-            }
-        }
-        Err(e) => {
-            ui::error(&format!("Failed to start noctalia-shell: {}", e));
-            ui::info("Make sure 'qs' (quickshell) is installed and available in your PATH.");
-            std::process::exit(1);
+    // [run] env from the config first, so --env can override single keys
+    for (key, value) in cfg.run.env.iter().chain(env.iter().map(|(k, v)| (k, v))) {
+        if ui::is_verbose() {
+            ui::info(&format!("Setting {}={}", key, value));
         }
+        cmd.env(key, value);
+    }
+
+    if !wait {
+        // Only returns if quickshell could not be started
+        let e = cmd.exec();
+        return Err(CliError::failure(format!("Failed to start noctalia-shell: {}", e))
+            .with_hint("Make sure 'qs' (quickshell) is installed and available in your PATH."));
     }
-}
 
+    cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().map_err(|e| {
+        CliError::failure(format!("Failed to start noctalia-shell: {}", e))
+            .with_hint("Make sure 'qs' (quickshell) is installed and available in your PATH.")
+    })?;
+    let tee = child.stderr.take().map(|stderr| tee_stderr(stderr, true));
+    let status = child
+        .wait()
+        .map_err(|e| CliError::failure(format!("Failed to wait for noctalia-shell: {}", e)))?;
+    let tail = tee.and_then(|t| t.join().ok()).unwrap_or_default();
+
+    if status.success() {
+        return Ok(());
+    }
+
+    let (code, message) = match status.code() {
+        Some(code) => (code, format!("noctalia-shell exited with status {}", code)),
+        None => (1, "noctalia-shell was killed by a signal".to_string()),
+    };
+    // The output itself was already streamed; point at the likely culprit
+    let err = CliError::new(ExitCode::Child(code), message);
+    Err(report_likely_causes(err, &tail))
+}
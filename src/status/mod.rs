@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use crate::archive;
+use crate::config;
+use crate::error::CliResult;
+use crate::output::{self, FilesState, LastCheck, StatusReport};
+use crate::run::shell::is_noctalia_running;
+use crate::ui;
+use crate::update::shell::{display_version, now_secs};
+
+/// Compare the installed tree against the fingerprint recorded at install time
+fn drift(path: &Path, recorded: Option<String>) -> Result<FilesState, String> {
+    let Some(recorded) = recorded else {
+        return Ok(FilesState::Untracked);
+    };
+    match archive::fingerprint(path) {
+        Ok(current) if current == recorded => Ok(FilesState::Unchanged),
+        Ok(_) => Ok(FilesState::Modified),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn report_drift(drift: &Result<FilesState, String>) {
+    match drift {
+        Ok(FilesState::Untracked) => ui::info("Local files: not tracked (installed before fingerprinting)"),
+        Ok(FilesState::Unchanged) => ui::info("Local files: unchanged since install"),
+        Ok(FilesState::Modified) => {
+            ui::warn("Local files: modified since install");
+            ui::info("Reinstall with 'noctalia install shell --reinstall' to restore them.");
+        }
+        Ok(FilesState::Unknown) => {}
+        Err(e) => ui::warn(&format!("Local files: could not be checked ({})", e)),
+    }
+}
+
+/// Summarize what the config and filesystem know about `component`
+pub fn run(component: &str) -> CliResult {
+    config::check_component(component)?;
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    let mut report = StatusReport { component: component.to_string(), ..Default::default() };
+    if !cfg.is_component_installed(component) {
+        if ui::is_json() {
+            return output::print(&report);
+        }
+        ui::section(&format!("Noctalia Status ({})", component));
+        ui::info("Installed: no");
+        ui::info(&format!("Install it with: noctalia install {}", component));
+        return Ok(());
+    }
+
+    let source = cfg.get_component_source(component).unwrap_or_default();
+    let default = cfg.get_default_source(component);
+    let version = cfg.get_component_version(component);
+    let path = config::find_shell_path();
+    let drift = path.as_deref().map(|path| drift(path, cfg.get_fingerprint(component)));
+    let running = is_noctalia_running();
+    let staged = cfg.get_staged_update(component);
+    let check = cfg.get_last_checked(component);
+
+    if ui::is_json() {
+        report.installed = true;
+        report.source = Some(source.to_string());
+        report.default_source = default.map(|d| d.to_string());
+        report.version = version;
+        report.commit = cfg.get_component_commit(component);
+        report.path = path.map(|p| p.display().to_string());
+        report.files = drift.map(|d| d.unwrap_or(FilesState::Unknown));
+        report.running = running;
+        report.staged_update = staged.map(|s| s.version);
+        report.last_check =
+            check.map(|c| LastCheck { checked_at: c.checked_at, source: c.source.to_string(), latest: c.latest });
+        return output::print(&report);
+    }
+
+    ui::section(&format!("Noctalia Status ({})", component));
+    ui::info("Installed: yes");
+    match default {
+        Some(default) if default != source => ui::info(&format!("Source: {} (default: {})", source, default)),
+        _ => ui::info(&format!("Source: {}", source)),
+    }
+    match version {
+        Some(version) => ui::info(&format!("Version: {}", display_version(source, &version))),
+        None => ui::info("Version: unknown (installed before version tracking)"),
+    }
+    match (path, drift) {
+        (Some(path), Some(drift)) => {
+            ui::info(&format!("Path: {}", path.display()));
+            report_drift(&drift);
+        }
+        _ => ui::warn("Path: installation directory not found"),
+    }
+    ui::info(&format!("Running: {}", if running { "yes" } else { "no" }));
+
+    if let Some(staged) = staged {
+        ui::info(&format!("Staged update: {}", display_version(staged.source, &staged.version)));
+    }
+    if let Some(check) = check {
+        let minutes = now_secs().saturating_sub(check.checked_at) / 60;
+        ui::info(&format!(
+            "Last update check: {} minute(s) ago, latest {}",
+            minutes,
+            display_version(check.source, &check.latest)
+        ));
+    }
+    Ok(())
+}
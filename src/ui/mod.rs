@@ -1,29 +1,255 @@
-use console::{style, Term};
+use std::{
+    io::{self, IsTerminal},
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use console::{style, StyledObject, Term};
+
+use crate::error::{CliError, CliResult};
+use crate::output;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable, decorated output
+    #[default]
+    Text,
+    /// Machine-readable output; errors are emitted as JSON on stderr
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Settings {
+    pub output: OutputFormat,
+    pub verbose: bool,
+    /// Answer yes to every confirmation prompt
+    pub yes: bool,
+    /// No glyphs, colors or rules; textual `[INFO]`-style prefixes instead
+    pub plain: bool,
+    /// Hide package manager output, keeping only our own summary lines
+    pub quiet: bool,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+pub fn init(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+fn settings() -> Settings {
+    SETTINGS.get().copied().unwrap_or_default()
+}
+
+pub fn output_format() -> OutputFormat {
+    settings().output
+}
+
+pub fn is_verbose() -> bool {
+    settings().verbose
+}
+
+pub fn assume_yes() -> bool {
+    settings().yes
+}
+
+/// Whether we can ask the user anything (stdin is a terminal)
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Prompts are drawn on stderr; keep them free of colors and glyphs under
+/// `--plain` or `--quiet`, or when stderr is not a terminal, e.g. a log file
+fn plain_prompts() -> bool {
+    is_plain() || is_quiet() || !io::stderr().is_terminal()
+}
+
+/// Ask a yes/no question; always true under `--yes`. Fails instead of
+/// guessing when there is no terminal to ask on.
+pub fn confirm(prompt: &str, default: bool) -> CliResult<bool> {
+    if assume_yes() {
+        return Ok(true);
+    }
+    if !is_interactive() {
+        return Err(CliError::usage(format!("'{}' needs an answer but stdin is not a terminal", prompt))
+            .with_hint("Re-run with --yes to accept."));
+    }
+    let colorful = dialoguer::theme::ColorfulTheme::default();
+    let theme: &dyn dialoguer::theme::Theme = if plain_prompts() { &dialoguer::theme::SimpleTheme } else { &colorful };
+    dialoguer::Confirm::with_theme(theme)
+        .with_prompt(prompt)
+        .default(default)
+        .interact()
+        .map_err(|e| CliError::failure(format!("Failed to read answer: {}", e)))
+}
+
+/// Let the user pick one of `items`; `None` if they cancel with Esc. Like
+/// `confirm`, fails when there is no terminal; `--yes` is left to the caller.
+pub fn select(prompt: &str, items: &[&str], default: usize) -> CliResult<Option<usize>> {
+    if !is_interactive() {
+        return Err(CliError::usage(format!("'{}' needs an answer but stdin is not a terminal", prompt)));
+    }
+    let colorful = dialoguer::theme::ColorfulTheme::default();
+    let theme: &dyn dialoguer::theme::Theme = if plain_prompts() { &dialoguer::theme::SimpleTheme } else { &colorful };
+    dialoguer::Select::with_theme(theme)
+        .with_prompt(prompt)
+        .items(items)
+        .default(default)
+        .interact_opt()
+        .map_err(|e| CliError::failure(format!("Failed to read choice: {}", e)))
+}
+
+pub fn is_quiet() -> bool {
+    settings().quiet
+}
+
+pub fn is_plain() -> bool {
+    settings().plain
+}
+
+/// Whether `--output json` was given: commands print their results as JSON
+/// and the decorated text output is suppressed
+pub fn is_json() -> bool {
+    output_format() == OutputFormat::Json
+}
+
+const RULE_WIDTH_DEFAULT: usize = 40;
+const RULE_WIDTH_MIN: usize = 20;
+const RULE_WIDTH_MAX: usize = 100;
+
+fn rule_width(term: &Term) -> usize {
+    // size_checked() is None when stdout is not a terminal (e.g. piped)
+    match term.size_checked() {
+        Some((_rows, cols)) => usize::from(cols).clamp(RULE_WIDTH_MIN, RULE_WIDTH_MAX),
+        None => RULE_WIDTH_DEFAULT,
+    }
+}
+
+/// Nesting depth of the current `scope`
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+fn indent() -> String {
+    "  ".repeat(DEPTH.load(Ordering::Relaxed))
+}
+
+/// Prefix `message` with the styled `glyph`, or with `[LABEL]` in plain mode,
+/// indented to the current scope
+fn prefixed(glyph: StyledObject<&str>, label: &str, message: &str) -> String {
+    if is_plain() {
+        format!("{}[{}] {}", indent(), label, message)
+    } else {
+        format!("{}{} {}", indent(), glyph, message)
+    }
+}
+
+/// Restores the depth when a scope ends, even by panicking
+struct ScopeGuard;
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        DEPTH.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Run one phase of a flow: `title` as a step, everything printed by `f`
+/// indented beneath it, then a closing done/failed line
+pub fn scope<T, E>(title: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    step(title);
+    let result = {
+        DEPTH.fetch_add(1, Ordering::Relaxed);
+        let _guard = ScopeGuard;
+        f()
+    };
+    match &result {
+        Ok(_) => success(&format!("{}: done", title)),
+        Err(_) => error(&format!("{}: failed", title)),
+    }
+    result
+}
 
 pub fn section(title: &str) {
+    if is_json() { return; }
     let term = Term::stdout();
-    let line = "━".repeat(40);
+    if is_plain() {
+        let _ = term.write_line(&format!("== {} ==", title));
+        return;
+    }
+    let line = "━".repeat(rule_width(&term));
     let _ = term.write_line(&format!("{}\n{}\n{}", style(&line).dim(), style(title).bold(), style(&line).dim()));
 }
 
 pub fn step(message: &str) {
+    if is_json() { return; }
     let term = Term::stdout();
-    let _ = term.write_line(&format!("{} {}", style("→").bold(), message));
+    let _ = term.write_line(&prefixed(style("→").bold(), "STEP", message));
 }
 
 pub fn success(message: &str) {
+    if is_json() { return; }
     let term = Term::stdout();
-    let _ = term.write_line(&format!("{} {}", style("✔").green().bold(), message));
+    let _ = term.write_line(&prefixed(style("✔").green().bold(), "OK", message));
 }
 
 pub fn info(message: &str) {
+    if is_json() { return; }
+    let term = Term::stdout();
+    let _ = term.write_line(&prefixed(style("i").cyan().bold(), "INFO", message));
+}
+
+pub fn item(message: &str) {
+    if is_json() { return; }
     let term = Term::stdout();
-    let _ = term.write_line(&format!("{} {}", style("i").cyan().bold(), message));
+    let bullet = if is_plain() { "-" } else { "•" };
+    let _ = term.write_line(&format!("{}  {} {}", indent(), bullet, message));
+}
+
+pub fn blank() {
+    if is_json() { return; }
+    let _ = Term::stdout().write_line("");
+}
+
+pub fn warn(message: &str) {
+    if is_json() { return; }
+    warn_always(message);
+}
+
+/// `warn` that is printed under `--output json` too, for warnings that must
+/// never be lost; stdout stays clean for the JSON document
+pub fn warn_always(message: &str) {
+    let term = Term::stderr();
+    let _ = term.write_line(&prefixed(style("!").yellow().bold(), "WARN", message));
 }
 
 pub fn error(message: &str) {
+    if is_json() { return; }
     let term = Term::stderr();
-    let _ = term.write_line(&format!("{} {}", style("x").red().bold(), message));
+    let _ = term.write_line(&prefixed(style("x").red().bold(), "ERROR", message));
 }
 
+/// Final "Completed in 4.2s" line, printed under --verbose only
+pub fn elapsed(duration: Duration, ok: bool) {
+    if is_json() || !is_verbose() { return; }
+    let message = format!("{} {:.1}s", if ok { "Completed in" } else { "Failed after" }, duration.as_secs_f64());
+    let _ = Term::stderr().write_line(&style(message).dim().to_string());
+}
+
+pub fn report_error(err: &CliError) {
+    if err.message.is_empty() {
+        return;
+    }
+    if is_json() {
+        let report = output::ErrorReport {
+            schema_version: output::SCHEMA_VERSION,
+            error: output::ErrorBody { code: err.code.code(), message: &err.message },
+        };
+        let _ = Term::stderr().write_line(&serde_json::to_string(&report).unwrap_or_default());
+        return;
+    }
 
+    error(&err.message);
+    for hint in &err.hints {
+        info(hint);
+    }
+}
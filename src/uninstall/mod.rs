@@ -0,0 +1,56 @@
+use crate::config;
+use crate::error::{CliError, CliResult};
+use crate::install::systemd;
+use crate::purge;
+use crate::run::shell::is_noctalia_running;
+use crate::ui;
+
+/// Remove the installed shell and mark it uninstalled in the config. The
+/// systemd user unit is removed too unless `keep_service` is set, so it is not
+/// left pointing at a deleted installation.
+pub fn run(component: &str, keep_service: bool) -> CliResult {
+    config::check_component(component)?;
+    ui::section("Uninstall Noctalia Shell");
+
+    let shell = config::find_shell_path();
+    let service = !keep_service && systemd::is_installed();
+    if shell.is_none() && !service {
+        ui::success("Nothing to uninstall");
+        return Ok(());
+    }
+
+    ui::info("The following will be removed:");
+    if let Some(path) = &shell {
+        ui::item(&path.display().to_string());
+    }
+    if service {
+        ui::item(&format!("{} (stopped and disabled first)", systemd::unit_path().display()));
+    } else if keep_service && systemd::is_installed() {
+        ui::info("Keeping the systemd service (--keep-service)");
+    }
+    if is_noctalia_running() {
+        ui::warn("noctalia-shell is still running; it keeps running until stopped");
+    }
+    if !ui::confirm("Uninstall noctalia-shell?", false)? {
+        ui::info("Nothing was removed");
+        return Ok(());
+    }
+
+    if service {
+        systemd::uninstall()?;
+    }
+
+    if let Some(path) = &shell {
+        ui::step(&format!("Removing {}", path.display()));
+        purge::remove(path).map_err(|e| CliError::failure(format!("Failed to remove {}: {}", path.display(), e)))?;
+        ui::success(&format!("Removed {}", path.display()));
+    }
+
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    cfg.set_installed(component, false);
+    cfg.set_staged_update(component, None);
+    cfg.save_or_warn(&path);
+
+    ui::success("Noctalia shell uninstalled");
+    Ok(())
+}
@@ -1,6 +1,19 @@
 pub mod shell;
 
+use std::time::Duration;
 
-
-
-
+/// Parse a duration such as `90s`, `30m`, `6h` or `2d` (bare numbers are seconds)
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown duration unit '{}' (use s, m, h or d)", unit)),
+    };
+    Ok(Duration::from_secs(number.saturating_mul(multiplier)))
+}
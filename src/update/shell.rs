@@ -1,357 +1,455 @@
-use std::{env, fs, path::PathBuf, process::Command};
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::SourceKind;
-use crate::config;
+use crate::archive;
+use crate::config::{self, SourceSpec, StagedUpdate, UpdateCheck};
+use crate::error::{CliError, CliResult};
+use crate::http::{self, codeload_url, get_latest_commit_sha, get_latest_release_info, get_tag_commit_sha};
+use crate::install::shell::{Resolved, Tarball, resolve};
+use crate::interrupt;
+use crate::progress::{Level, ProgressSink};
 use crate::ui;
 
-const REPO_API: &str = "https://api.github.com/repos/noctalia-dev/noctalia-shell";
-const REPO_CODELOAD_MAIN: &str = "https://codeload.github.com/noctalia-dev/noctalia-shell/tar.gz/refs/heads/main";
-
-fn find_installation_path() -> Option<PathBuf> {
-    // Check both possible installation locations
-    let old_path = PathBuf::from("/etc/xdg/quickshell/noctalia-shell");
-    let home = env::var("HOME").ok()?;
-    let new_path = PathBuf::from(&home).join(".config/quickshell/noctalia-shell");
-    
-    if old_path.exists() {
-        Some(old_path)
-    } else if new_path.exists() {
-        Some(new_path)
-    } else {
-        None
+/// A newer version found by `check`, with its commit once known
+struct Available {
+    version: String,
+    commit: Option<String>,
+}
+
+impl Available {
+    /// Resolve the commit of a release tag (a git version is its own commit),
+    /// so the download is pinned to the commit that gets recorded
+    fn pinned(mut self, source: SourceKind) -> Self {
+        if self.commit.is_none() {
+            self.commit = resolve_commit(source, &self.version);
+        }
+        self
+    }
+
+    fn tarball_url(&self, source: SourceKind) -> String {
+        match source {
+            SourceKind::Git => codeload_url(&self.version),
+            SourceKind::Release => http::release_tarball_url(&self.version, self.commit.as_deref()),
+        }
     }
 }
 
-#[derive(serde::Deserialize)]
-struct ReleaseInfo { 
-    tag_name: String, 
-    tarball_url: String 
+/// Flags of `update shell` that shape how the update runs
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Stage the update in the cache instead of applying it
+    pub download_only: bool,
+    /// Ignore cached checks
+    pub force: bool,
+    /// Write through a symlinked installation directory into its target
+    pub follow_symlink: bool,
+    /// Reuse a previous check younger than this
+    pub max_age: Option<Duration>,
+    /// Print the commits between the installed and latest git revision
+    pub changelog: bool,
 }
 
-#[derive(serde::Deserialize)]
-struct CommitInfo {
-    sha: String,
+/// Update the shell to what `spec` points at: the latest version of its
+/// channel, or a fixed release or git revision
+pub fn run(spec: SourceSpec, opts: Options, sink: &mut dyn ProgressSink) -> CliResult {
+    let _defer = interrupt::defer();
+    let result = update(spec, opts, sink);
+    sink.finished(&result);
+    result
 }
 
-pub fn run(source: SourceKind) {
+fn update(spec: SourceSpec, opts: Options, sink: &mut dyn ProgressSink) -> CliResult {
     ui::section("Update Noctalia Shell");
-    
+    if matches!(spec, SourceSpec::Url(_) | SourceSpec::LocalFile(_)) {
+        return Err(CliError::usage(format!("Cannot update from {}", spec))
+            .with_hint("Install a custom tarball with 'noctalia install shell --from-url' or '--from-file'."));
+    }
+    let source = spec.kind();
+
     // Check if shell is installed
     let (cfg, _path) = config::CliConfig::load().expect("load config");
     if !cfg.is_component_installed("shell") {
-        ui::error("Noctalia shell is not installed. Run 'noctalia install shell' first.");
-        std::process::exit(1);
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
     }
 
     let installed_version = cfg.get_component_version("shell");
+    // What is installed, for display; `spec` (already resolved with flag
+    // precedence) decides what we update to
     let installed_source = cfg.get_component_source("shell").unwrap_or(source);
 
-    ui::info(&format!("Current source: {}", installed_source));
+    sink.message(Level::Info, &format!("Current source: {}", installed_source));
+    if source != installed_source {
+        sink.message(Level::Info, &format!("Switching to {}", source));
+    }
     if let Some(ref ver) = installed_version {
         match installed_source {
             SourceKind::Git => {
-                let display = if ver.len() >= 8 { &ver[..8] } else { ver.as_str() };
-                ui::info(&format!("Installed commit: {}", display));
+                sink.message(Level::Info, &format!("Installed: {}", display_version(SourceKind::Git, ver)));
             }
-            SourceKind::Release => ui::info(&format!("Installed version: {}", ver)),
+            SourceKind::Release => match cfg.get_component_commit("shell") {
+                Some(commit) => {
+                    let display = display_version(SourceKind::Git, &commit);
+                    sink.message(Level::Info, &format!("Installed version: {} ({})", ver, display));
+                }
+                None => sink.message(Level::Info, &format!("Installed version: {}", ver)),
+            },
         }
     } else {
-        ui::info("Installed version: unknown (installed before version tracking)");
+        sink.message(Level::Info, "Installed version: unknown (installed before version tracking)");
     }
 
-    ui::step("Checking for updates");
-
-    let (latest_version, needs_update) = match source {
-        SourceKind::Git => {
-            ui::info("Fetching latest commit from git main");
-            let latest_sha = match get_latest_commit_sha() {
-                Ok(sha) => sha,
-                Err(e) => {
-                    ui::error(&format!("Failed to fetch latest commit: {}", e));
-                    std::process::exit(1);
-                }
-            };
-            let display = if latest_sha.len() >= 8 { &latest_sha[..8] } else { latest_sha.as_str() };
-            ui::info(&format!("Latest commit: {}", display));
-            
-            let needs_update = installed_version.as_ref().map(|v| v != &latest_sha).unwrap_or(true);
-            (latest_sha, needs_update)
+    let available = match &spec {
+        SourceSpec::ReleaseTag(tag) => pinned(tag, installed_version.as_deref(), sink)?,
+        SourceSpec::LatestRelease | SourceSpec::GitMain => {
+            // --force always asks GitHub, ignoring a recent cached check
+            let max_age = opts.max_age.filter(|_| !opts.force);
+            check(source, installed_version.as_deref(), max_age, sink)?
         }
-        SourceKind::Release => {
-            ui::info("Fetching latest release");
-            let release_info = match get_latest_release_info() {
-                Ok(info) => info,
-                Err(e) => {
-                    ui::error(&format!("Failed to fetch latest release: {}", e));
-                    std::process::exit(1);
-                }
-            };
-            ui::info(&format!("Latest release: {}", release_info.tag_name));
-            
-            let needs_update = installed_version.as_ref().map(|v| v != &release_info.tag_name).unwrap_or(true);
-            (release_info.tag_name, needs_update)
+        _ => revision(&spec, installed_version.as_deref(), sink)?,
+    };
+    let Some(available) = available else {
+        match &spec {
+            SourceSpec::ReleaseTag(tag) => sink.message(Level::Success, &format!("Noctalia shell is already at {}", tag)),
+            _ if spec.is_latest() => sink.message(Level::Success, "Noctalia shell is already up to date!"),
+            _ => sink.message(Level::Success, &format!("Noctalia shell is already at {}", spec)),
         }
+        return Ok(());
     };
 
-    if !needs_update {
-        ui::success("Noctalia shell is already up to date!");
-        return;
+    if opts.changelog && source == SourceKind::Git && installed_source == SourceKind::Git {
+        match installed_version.as_deref() {
+            Some(installed) => print_changelog(installed, &available.version),
+            None => sink.message(Level::Info, "Skipping changelog: installed commit is unknown"),
+        }
     }
 
-    ui::step("Update available, downloading...");
-
-    match source {
-        SourceKind::Git => {
-            if let Err(e) = download_and_extract_git_main() {
-                ui::error(&format!("Failed to update noctalia-shell (git): {}", e));
-                std::process::exit(1);
-            }
-        }
-        SourceKind::Release => {
-            if let Err(e) = download_and_extract_latest_release() {
-                ui::error(&format!("Failed to update noctalia-shell (release): {}", e));
-                std::process::exit(1);
-            }
-        }
+    let available = available.pinned(source);
+    if opts.download_only {
+        return stage(source, available, sink);
     }
 
-    let (mut cfg, path) = config::CliConfig::load().expect("load config");
-    cfg.set_component_source("shell", source);
-    cfg.set_component_version("shell", latest_version.clone());
-    let _ = cfg.save(&path);
+    let target = install_target(opts.follow_symlink)?;
+    sink.phase("Update available, downloading...");
 
-    let version_display = match source {
-        SourceKind::Git => {
-            let display = if latest_version.len() >= 8 { &latest_version[..8] } else { latest_version.as_str() };
-            format!("commit {}", display)
-        }
-        SourceKind::Release => latest_version,
+    let name = match &spec {
+        SourceSpec::GitMain => "main",
+        _ if source == SourceKind::Git => available.version.get(..8).unwrap_or(&available.version),
+        _ => available.version.as_str(),
     };
-    ui::success(&format!("Successfully updated noctalia-shell to {}", version_display));
+    let archive = http::downloads_dir().join(format!("noctalia-shell-{}.tar.gz", name));
+    let url = available.tarball_url(source);
+    http::download_with_progress(&url, &archive, &mut |done, total| sink.bytes(done, total))
+        .map_err(|e| e.into_cli(&format!("Failed to download noctalia-shell ({})", source)))?;
+
+    apply(&archive, Some(&url), &target, source, available.version, available.commit, sink)?;
+    http::prune_downloads();
+    Ok(())
+}
+
+/// Extract the tarball staged by `update shell --download-only`. Makes no
+/// network requests.
+pub fn apply_staged(follow_symlink: bool, sink: &mut dyn ProgressSink) -> CliResult {
+    let _defer = interrupt::defer();
+    let result = apply_staged_update(follow_symlink, sink);
+    sink.finished(&result);
+    result
 }
 
-fn downloads_dir() -> PathBuf {
-    let home = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-    let path = PathBuf::from(home).join("Downloads");
-    if let Err(e) = fs::create_dir_all(&path) {
-        eprintln!("Warning: could not create Downloads dir ({}), falling back to /tmp", e);
-        return PathBuf::from("/tmp");
+fn apply_staged_update(follow_symlink: bool, sink: &mut dyn ProgressSink) -> CliResult {
+    ui::section("Apply Staged Update");
+
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    if !cfg.is_component_installed("shell") {
+        return Err(CliError::failure("Noctalia shell is not installed. Run 'noctalia install shell' first."));
     }
-    path
+    let Some(staged) = cfg.get_staged_update("shell") else {
+        return Err(CliError::failure("No staged update for noctalia-shell.")
+            .with_hint("Run 'noctalia update shell --download-only' to stage one."));
+    };
+    if !staged.archive.exists() {
+        return Err(CliError::failure(format!("Staged archive is missing: {}", staged.archive.display()))
+            .with_hint("Run 'noctalia update shell --download-only' again to re-stage it."));
+    }
+
+    sink.message(Level::Info, &format!("Staged {} update: {}", staged.source, display_version(staged.source, &staged.version)));
+    let target = install_target(follow_symlink)?;
+    apply(&staged.archive, None, &target, staged.source, staged.version, staged.commit, sink)?;
+
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    cfg.set_staged_update("shell", None);
+    cfg.save_or_warn(&path);
+    let _ = fs::remove_file(&staged.archive);
+    Ok(())
 }
 
-fn http_client() -> reqwest::blocking::Client {
-    reqwest::blocking::Client::builder()
-        .user_agent("noctalia-cli (+https://github.com/noctalia-dev/noctalia)")
-        .build()
-        .expect("failed to build http client")
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
-fn get_latest_commit_sha() -> Result<String, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let url = format!("{}/commits/main", REPO_API);
-    let commit: CommitInfo = client.get(url).send()?.json()?;
-    Ok(commit.sha)
+/// Reuse the last check for `source` if it happened within `max_age`
+fn cached_check(source: SourceKind, max_age: Duration) -> Option<Available> {
+    let (cfg, _path) = config::CliConfig::load().ok()?;
+    let last = cfg.get_last_checked("shell").filter(|c| c.source == source)?;
+    let age = now_secs().saturating_sub(last.checked_at);
+    if age > max_age.as_secs() {
+        return None;
+    }
+
+    ui::info(&format!("Using update check from {} minute(s) ago", age / 60));
+    Some(Available { version: last.latest, commit: None })
 }
 
-fn get_latest_release_info() -> Result<ReleaseInfo, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let url = format!("{}/releases/latest", REPO_API);
-    let info: ReleaseInfo = client.get(url).send()?.json()?;
-    Ok(info)
+/// Order two release tags as semver, ignoring a leading `v`. Tags that are
+/// not semver can only be told equal (`Some(Equal)`) or different (`None`).
+pub(crate) fn compare_tags(a: &str, b: &str) -> Option<Ordering> {
+    let parse = |tag: &str| semver::Version::parse(tag.trim().trim_start_matches(['v', 'V'])).ok();
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => Some(a.cmp_precedence(&b)),
+        _ if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
 }
 
-fn download_git_main() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let resp = client.get(REPO_CODELOAD_MAIN).send()?;
-    if !resp.status().is_success() { return Err(format!("http {}", resp.status()).into()); }
-    let bytes = resp.bytes()?;
-    let out = downloads_dir().join("noctalia-shell-main.tar.gz");
-    fs::write(&out, &bytes)?;
-    Ok(out)
+/// Whether `installed` needs no update to `latest`. A release newer than the
+/// latest one (say the latest was yanked) is reported and kept.
+fn is_current(source: SourceKind, installed: Option<&str>, latest: &str, sink: &mut dyn ProgressSink) -> bool {
+    let Some(installed) = installed else {
+        return false;
+    };
+    match source {
+        SourceKind::Git => installed == latest,
+        SourceKind::Release => match compare_tags(installed, latest) {
+            Some(Ordering::Equal) => true,
+            Some(Ordering::Greater) => {
+                sink.message(Level::Info, &format!("Installed {} is newer than the latest release {}", installed, latest));
+                true
+            }
+            _ => false,
+        },
+    }
 }
 
-fn download_latest_release() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let client = http_client();
-    let info = get_latest_release_info()?;
-    let resp = client.get(info.tarball_url).send()?;
-    if !resp.status().is_success() { return Err(format!("http {}", resp.status()).into()); }
-    let bytes = resp.bytes()?;
-    let filename = format!("noctalia-shell-{}.tar.gz", info.tag_name);
-    let out = downloads_dir().join(filename);
-    fs::write(&out, &bytes)?;
-    Ok(out)
+/// Number of recent releases offered by `update --interactive`
+const PICKER_RELEASES: u32 = 20;
+
+/// Let the user choose a release tag for `update --interactive`. `None`
+/// without a terminal, in which case the normal latest-version update runs.
+pub fn pick_release() -> CliResult<Option<String>> {
+    if !ui::is_interactive() {
+        ui::info("stdin is not a terminal; updating to the latest version instead");
+        return Ok(None);
+    }
+
+    let releases = http::get_releases(PICKER_RELEASES).map_err(|e| e.into_cli("Failed to fetch releases"))?;
+    if releases.is_empty() {
+        return Err(CliError::failure("No releases found"));
+    }
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    let installed = cfg.get_component_version("shell").filter(|_| cfg.get_component_source("shell") == Some(SourceKind::Release));
+
+    let items: Vec<String> = releases
+        .iter()
+        .map(|r| {
+            let mut item = r.tag_name.clone();
+            if let Some(date) = r.published_at.as_deref() {
+                item.push_str(&format!("  {}", date.get(..10).unwrap_or(date)));
+            }
+            if r.prerelease {
+                item.push_str("  (prerelease)");
+            }
+            if installed.as_deref().and_then(|v| compare_tags(v, &r.tag_name)) == Some(Ordering::Equal) {
+                item.push_str("  (installed)");
+            }
+            item
+        })
+        .collect();
+    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+    match ui::select("Choose the version to update to", &items, 0)? {
+        Some(index) => Ok(Some(releases[index].tag_name.clone())),
+        None => Err(CliError::failure("No version chosen")),
+    }
 }
 
-fn download_and_extract_git_main() -> Result<(), Box<dyn std::error::Error>> {
-    let archive = download_git_main()?;
-    extract(&archive)?;
-    let _ = fs::remove_file(&archive);
-    Ok(())
+/// Look up release `tag` for `update --to`; `None` when it is already installed
+fn pinned(tag: &str, installed_version: Option<&str>, sink: &mut dyn ProgressSink) -> CliResult<Option<Available>> {
+    sink.phase(&format!("Fetching release {}", tag));
+    let release_info = http::get_release_by_tag(tag).map_err(|e| e.into_cli(&format!("Failed to fetch release {}", tag)))?;
+
+    if let Some(installed) = installed_version {
+        match compare_tags(&release_info.tag_name, installed) {
+            Some(Ordering::Equal) => return Ok(None),
+            Some(Ordering::Less) => {
+                sink.message(Level::Warn, &format!("Downgrading from {} to {}", installed, release_info.tag_name));
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(Available { version: release_info.tag_name, commit: None }))
 }
 
-fn download_and_extract_latest_release() -> Result<(), Box<dyn std::error::Error>> {
-    let archive = download_latest_release()?;
-    extract(&archive)?;
-    let _ = fs::remove_file(&archive);
-    Ok(())
+/// Look up the commit a git branch or revision points at; `None` when it is
+/// already installed
+fn revision(spec: &SourceSpec, installed_version: Option<&str>, sink: &mut dyn ProgressSink) -> CliResult<Option<Available>> {
+    let Resolved { version, commit, tarball } = resolve(spec, sink)?;
+    if matches!(tarball, Tarball::File(_)) {
+        return Err(CliError::usage(format!("Cannot update from {}", spec)));
+    }
+    if installed_version == Some(version.as_str()) {
+        return Ok(None);
+    }
+    Ok(Some(Available { version, commit }))
 }
 
-fn extract(archive_path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    // Find where the shell is actually installed
-    let target = match find_installation_path() {
-        Some(path) => {
-            ui::info(&format!("Found installation at: {}", path.display()));
-            path
+/// Look up the latest version for `source`; `None` when already up to date
+fn check(
+    source: SourceKind,
+    installed_version: Option<&str>,
+    max_age: Option<Duration>,
+    sink: &mut dyn ProgressSink,
+) -> CliResult<Option<Available>> {
+    sink.phase("Checking for updates");
+
+    if let Some(available) = max_age.and_then(|max_age| cached_check(source, max_age)) {
+        sink.message(Level::Info, &format!("Latest version: {}", display_version(source, &available.version)));
+        if is_current(source, installed_version, &available.version, sink) {
+            return Ok(None);
         }
-        None => {
-            // If not found, use the new default location
-            let home = env::var("HOME").expect("HOME environment variable not set");
-            let new_path = PathBuf::from(home).join(".config/quickshell/noctalia-shell");
-            ui::info(&format!("No existing installation found, will install to: {}", new_path.display()));
-            new_path
+        return Ok(Some(available));
+    }
+
+    let available = match source {
+        SourceKind::Git => {
+            sink.message(Level::Info, "Fetching latest commit from git main");
+            let latest_sha = get_latest_commit_sha().map_err(|e| e.into_cli("Failed to fetch latest commit"))?;
+            sink.message(Level::Info, &format!("Latest: {}", display_version(SourceKind::Git, &latest_sha)));
+            Available { commit: Some(latest_sha.clone()), version: latest_sha }
         }
-    };
-    
-    // Check if we need sudo for the old location
-    let needs_sudo = target.starts_with("/etc");
-    
-    // Remove existing directory if it exists
-    if target.exists() {
-        if needs_sudo {
-            // Use sudo to remove the directory
-            let target_str = target.to_str().unwrap();
-            let status = Command::new("sudo")
-                .args(["rm", "-rf", target_str])
-                .status()?;
-            if !status.success() {
-                return Err("Failed to remove existing installation".into());
-            }
-        } else {
-            fs::remove_dir_all(&target)?;
+        SourceKind::Release => {
+            sink.message(Level::Info, "Fetching latest release");
+            let release_info = get_latest_release_info().map_err(|e| e.into_cli("Failed to fetch latest release"))?;
+            sink.message(Level::Info, &format!("Latest release: {}", release_info.tag_name));
+            Available { version: release_info.tag_name, commit: None }
         }
+    };
+
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    cfg.set_last_checked("shell", UpdateCheck { source, latest: available.version.clone(), checked_at: now_secs() });
+    cfg.save_or_warn(&path);
+
+    if is_current(source, installed_version, &available.version, sink) {
+        return Ok(None);
     }
-    
-    // Create parent directories
-    if let Some(parent) = target.parent() {
-        if needs_sudo {
-            let parent_str = parent.to_str().unwrap();
-            let status = Command::new("sudo")
-                .args(["mkdir", "-p", parent_str])
-                .status()?;
-            if !status.success() {
-                return Err("Failed to create parent directory".into());
-            }
-        } else {
-            fs::create_dir_all(parent)?;
-        }
+    Ok(Some(available))
+}
+
+/// Download the update into the cache dir and record it in the config
+fn stage(source: SourceKind, available: Available, sink: &mut dyn ProgressSink) -> CliResult {
+    sink.phase("Update available, staging for later...");
+
+    let dir = config::cache_dir().join("staged");
+    fs::create_dir_all(&dir)
+        .map_err(|e| CliError::failure(format!("Failed to create cache dir {}: {}", dir.display(), e)))?;
+    let archive = dir.join(format!("noctalia-shell-{}.tar.gz", available.version));
+    http::download_to(&available.tarball_url(source), &archive)
+        .map_err(|e| e.into_cli(&format!("Failed to download noctalia-shell ({})", source)))?;
+
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    if let Some(previous) = cfg.get_staged_update("shell")
+        && previous.archive != archive
+    {
+        let _ = fs::remove_file(&previous.archive);
     }
-    
-    // Extract archive
-    if needs_sudo {
-        // For old location, extract to temp directory first, then move with sudo
-        let temp_dir = std::env::temp_dir().join(format!("noctalia-shell-update-{}", std::process::id()));
-        fs::create_dir_all(&temp_dir)?;
-        
-        let file = fs::File::open(archive_path)?;
-        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
-        archive.unpack(&temp_dir)?;
-        
-        // Move contents up one level (strip-components=1 equivalent)
-        let extracted_dir = temp_dir.join("noctalia-shell-main");
-        let temp_target = if extracted_dir.exists() {
-            // Move all contents from noctalia-shell-main to temp_target
-            let temp_target = temp_dir.join("noctalia-shell");
-            fs::create_dir_all(&temp_target)?;
-            for entry in fs::read_dir(&extracted_dir)? {
-                let entry = entry?;
-                let dest = temp_target.join(entry.file_name());
-                fs::rename(entry.path(), dest)?;
-            }
-            fs::remove_dir(&extracted_dir)?;
-            temp_target
-        } else {
-            // Try with release tag name pattern
-            let entries: Vec<_> = fs::read_dir(&temp_dir)?.collect();
-            if entries.len() == 1 {
-                if let Some(Ok(entry)) = entries.into_iter().next() {
-                    let entry_path = entry.path();
-                    if entry_path.is_dir() {
-                        let temp_target = temp_dir.join("noctalia-shell");
-                        fs::create_dir_all(&temp_target)?;
-                        // Move all contents from the single subdirectory to temp_target
-                        for sub_entry in fs::read_dir(&entry_path)? {
-                            let sub_entry = sub_entry?;
-                            let dest = temp_target.join(sub_entry.file_name());
-                            fs::rename(sub_entry.path(), dest)?;
-                        }
-                        fs::remove_dir(&entry_path)?;
-                        temp_target
-                    } else {
-                        temp_dir.clone()
-                    }
-                } else {
-                    temp_dir.clone()
-                }
-            } else {
-                temp_dir.clone()
-            }
-        };
-        
-        // Use sudo to move the extracted directory to the target
-        let temp_target_str = temp_target.to_str().unwrap();
-        let target_str = target.to_str().unwrap();
-        let cmd = format!("cp -r '{}'/* '{}'/ && rm -rf '{}'", temp_target_str, target_str, temp_target_str);
-        
-        ui::info("Elevating with sudo. You may be prompted for your password.");
-        let status = std::process::Command::new("sudo")
-            .args(["sh", "-c", &cmd])
-            .stdin(std::process::Stdio::inherit())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .status()?;
-        
-        // Clean up temp directory
-        let _ = fs::remove_dir_all(&temp_dir);
-        
-        if !status.success() {
-            return Err("Failed to install updated files".into());
+    cfg.set_staged_update("shell", Some(StagedUpdate {
+        source,
+        version: available.version.clone(),
+        commit: available.commit.clone(),
+        archive: archive.clone(),
+    }));
+    cfg.save_or_warn(&path);
+
+    sink.message(Level::Success, &format!("Staged {} at {}", display_version(source, &available.version), archive.display()));
+    sink.message(Level::Info, "Apply it later with: noctalia update shell --apply-staged");
+    Ok(())
+}
+
+fn print_changelog(from: &str, to: &str) {
+    let commits = match http::get_commits_between(from, to) {
+        Ok(commits) => commits,
+        Err(e) => {
+            ui::warn(&format!("Could not fetch changelog: {}", e));
+            return;
         }
-    } else {
-        // For new location, extract directly
-        let file = fs::File::open(archive_path)?;
-        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
-        archive.unpack(&target)?;
-        
-        // Move contents up one level (strip-components=1 equivalent)
-        let extracted_dir = target.join("noctalia-shell-main");
-        if extracted_dir.exists() {
-            // Move all contents from noctalia-shell-main to target
-            for entry in fs::read_dir(&extracted_dir)? {
-                let entry = entry?;
-                let dest = target.join(entry.file_name());
-                fs::rename(entry.path(), dest)?;
-            }
-            fs::remove_dir(&extracted_dir)?;
-        } else {
-            // Try with release tag name pattern
-            let entries: Vec<_> = fs::read_dir(&target)?.collect();
-            if entries.len() == 1 {
-                if let Some(Ok(entry)) = entries.into_iter().next() {
-                    let entry_path = entry.path();
-                    if entry_path.is_dir() {
-                        // Move all contents from the single subdirectory to target
-                        for sub_entry in fs::read_dir(&entry_path)? {
-                            let sub_entry = sub_entry?;
-                            let dest = target.join(sub_entry.file_name());
-                            fs::rename(sub_entry.path(), dest)?;
-                        }
-                        fs::remove_dir(&entry_path)?;
-                    }
-                }
+    };
+    ui::info(&format!("{} new commit(s):", commits.len()));
+    for (sha, summary) in &commits {
+        ui::item(&format!("{}: {}", display_version(SourceKind::Git, sha), summary));
+    }
+}
+
+fn resolve_commit(source: SourceKind, version: &str) -> Option<String> {
+    match source {
+        SourceKind::Git => Some(version.to_string()),
+        SourceKind::Release => match get_tag_commit_sha(version) {
+            Ok(sha) => Some(sha),
+            Err(e) => {
+                ui::warn(&format!("Could not resolve commit for {}: {}", version, e));
+                None
             }
+        },
+    }
+}
+
+/// `version` as shown to the user: the short commit for git, the tag for releases
+pub(crate) fn display_version(source: SourceKind, version: &str) -> String {
+    match source {
+        SourceKind::Git => {
+            let display = if version.len() >= 8 { &version[..8] } else { version };
+            format!("commit {}", display)
         }
+        SourceKind::Release => version.to_string(),
     }
-    
+}
+
+/// Extract `archive` over `target` and record the new version. With `url`, a
+/// corrupt archive is downloaded again once.
+fn apply(
+    archive: &Path,
+    url: Option<&str>,
+    target: &Path,
+    source: SourceKind,
+    version: String,
+    commit: Option<String>,
+    sink: &mut dyn ProgressSink,
+) -> CliResult {
+    // Replacing the installation is not interruptible; only start if we may finish
+    interrupt::check()?;
+    let extracted = match url {
+        Some(url) => archive::install_with_retry(archive, target, || http::download_to(url, archive).map_err(Into::into)),
+        None => archive::install_into(archive, target),
+    };
+    extracted.map_err(|e| CliError::failure(format!("Failed to update noctalia-shell ({}): {}", source, e)))?;
+
+    let (mut cfg, path) = config::CliConfig::load().expect("load config");
+    cfg.set_component_source("shell", source);
+    cfg.set_component_version("shell", version.clone());
+    cfg.set_component_commit("shell", commit);
+    cfg.set_fingerprint("shell", archive::fingerprint(target).ok());
+    cfg.save_or_warn(&path);
+
+    sink.message(Level::Success, &format!("Successfully updated noctalia-shell to {}", display_version(source, &version)));
     Ok(())
 }
+
+/// Where the update goes: the existing installation, or the default location
+fn install_target(follow_symlink: bool) -> CliResult<PathBuf> {
+    let target = config::find_shell_path().unwrap_or_else(config::shell_target_path);
+    let resolved = archive::resolve_target(&target, follow_symlink)?;
+    archive::report_target(&target, &resolved);
+    Ok(resolved)
+}
+
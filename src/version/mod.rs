@@ -0,0 +1,29 @@
+use crate::config;
+use crate::error::{CliError, CliResult, ExitCode};
+use crate::output::{self, VersionReport};
+use crate::ui;
+
+/// Print the installed version of `component` (tag or commit) on its own, for
+/// scripts
+pub fn run(component: &str) -> CliResult {
+    config::check_component(component)?;
+
+    let (cfg, _path) = config::CliConfig::load().expect("load config");
+    if !cfg.is_component_installed(component) {
+        return Err(CliError::new(ExitCode::NotFound, format!("{} is not installed", component)));
+    }
+    match cfg.get_component_version(component) {
+        Some(version) if ui::is_json() => output::print(&VersionReport {
+            component: component.to_string(),
+            version,
+            source: cfg.get_component_source(component).map(|s| s.to_string()),
+            commit: cfg.get_component_commit(component),
+        }),
+        Some(version) => {
+            println!("{}", version);
+            Ok(())
+        }
+        None => Err(CliError::new(ExitCode::NotFound, format!("The installed {} version is unknown", component))
+            .with_hint(format!("Run 'noctalia update {}' to start tracking it.", component))),
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+/// Run the CLI with `home` as its home, config and cache directories
+fn noctalia(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_noctalia"))
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn failing_command_prints_json_error_under_output_json() {
+    let home = tempfile::tempdir().unwrap();
+    let out = noctalia(home.path(), &["--output", "json", "config", "get", "no-such-key"]);
+
+    assert_eq!(out.status.code(), Some(2));
+    assert!(out.stdout.is_empty());
+    let report: serde_json::Value = serde_json::from_slice(&out.stderr).unwrap();
+    assert_eq!(report["schema_version"], 1);
+    assert_eq!(report["error"]["code"], 2);
+    assert_eq!(report["error"]["message"], "Unknown config key 'no-such-key'");
+}
+
+#[test]
+fn status_and_version_print_json_under_output_json() {
+    let home = tempfile::tempdir().unwrap();
+    let shell = home.path().join(".config/quickshell/noctalia-shell");
+    fs::create_dir_all(&shell).unwrap();
+    fs::write(shell.join("shell.qml"), "").unwrap();
+    let config = home.path().join("config/noctalia");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(
+        config.join("cli.toml"),
+        "[components.shell]\ninstalled = true\nsource = \"release\"\nversion = \"v2.1.0\"\ncommit = \"0123abcd\"\n",
+    )
+    .unwrap();
+
+    let out = noctalia(home.path(), &["--output", "json", "status"]);
+    assert!(out.status.success());
+    let status: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(status["schema_version"], 1);
+    assert_eq!(status["installed"], true);
+    assert_eq!(status["source"], "release");
+    assert_eq!(status["version"], "v2.1.0");
+    assert_eq!(status["files"], "untracked");
+
+    let out = noctalia(home.path(), &["--output", "json", "version"]);
+    assert!(out.status.success());
+    let version: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(version["schema_version"], 1);
+    assert_eq!(version["version"], "v2.1.0");
+    assert_eq!(version["commit"], "0123abcd");
+}